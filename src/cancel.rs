@@ -0,0 +1,57 @@
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+lazy_static::lazy_static! {
+    /// 正在处理的请求，键为请求 id 的 JSON 文本
+    static ref IN_FLIGHT: Mutex<HashMap<String, Arc<AtomicBool>>> = Mutex::new(HashMap::new());
+}
+
+thread_local! {
+    /// 当前线程正在处理的请求的取消标记，供长时间运行的操作轮询
+    static CURRENT: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// 请求处理期间持有，离开作用域时注销该请求
+pub struct InFlight {
+    key: String,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.lock().unwrap().remove(&self.key);
+        CURRENT.with(|current| *current.borrow_mut() = None);
+    }
+}
+
+/// 登记一个开始处理的请求，并将其取消标记设为当前线程的标记
+pub fn begin(id: &Value) -> InFlight {
+    let key = id.to_string();
+    let flag = Arc::new(AtomicBool::new(false));
+    IN_FLIGHT.lock().unwrap().insert(key.clone(), flag.clone());
+    CURRENT.with(|current| *current.borrow_mut() = Some(flag));
+    InFlight { key }
+}
+
+/// 响应 `notifications/cancelled`，返回该请求是否仍在处理中
+pub fn cancel(id: &Value) -> bool {
+    match IN_FLIGHT.lock().unwrap().get(&id.to_string()) {
+        Some(flag) => {
+            flag.store(true, Ordering::SeqCst);
+            true
+        }
+        None => false,
+    }
+}
+
+/// 当前线程处理的请求是否已被客户端取消
+pub fn is_cancelled() -> bool {
+    CURRENT.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::SeqCst))
+    })
+}
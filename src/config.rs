@@ -0,0 +1,138 @@
+use crate::message;
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// 仓库根目录下的项目配置文件名
+pub const PROJECT_CONFIG_FILE: &str = ".git-summarizer.toml";
+
+/// 单个字符串或字符串数组
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StringOrList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl StringOrList {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            StringOrList::One(s) => vec![s],
+            StringOrList::Many(v) => v,
+        }
+    }
+}
+
+/// `.git-summarizer.toml` 中的配置项，未出现的键保持服务器默认值
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub commit_format: Option<StringOrList>,
+    pub extra_constraints: Option<Vec<String>>,
+    pub body_line_limit: Option<usize>,
+    pub require_chinese_body: Option<bool>,
+    pub show_example: Option<bool>,
+    pub context_lines: Option<u32>,
+    pub max_diff_bytes: Option<usize>,
+    pub max_files: Option<usize>,
+    pub chunk_bytes: Option<usize>,
+    pub rename_threshold: Option<u16>,
+    pub exclude_patterns: Option<Vec<String>>,
+    pub lint_types: Option<Vec<String>>,
+    pub lint_header_max_length: Option<usize>,
+    pub templates: Option<BTreeMap<String, StringOrList>>,
+    pub default_template: Option<String>,
+}
+
+impl ProjectConfig {
+    /// 检查提交格式与各模板是否包含必需的占位符，出错时指出对应的键
+    fn validate(&self) -> Result<()> {
+        let formats = self
+            .commit_format
+            .iter()
+            .map(|f| ("commit_format".to_string(), f))
+            .chain(
+                self.templates
+                    .iter()
+                    .flatten()
+                    .map(|(name, f)| (format!("templates.{}", name), f)),
+            );
+        for (key, format) in formats {
+            let missing = message::missing_format_markers(&format.clone().into_vec());
+            if !missing.is_empty() {
+                bail!("{} 缺少必需的占位符: {}", key, missing.join(", "));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 读取仓库根目录下的项目配置，文件不存在时返回 `None`；格式或模板有误时返回错误
+pub fn load(root: &Path) -> Result<Option<ProjectConfig>> {
+    let path = root.join(PROJECT_CONFIG_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)?;
+    let project: ProjectConfig = toml::from_str(&content)?;
+    project.validate()?;
+    Ok(Some(project))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn missing_file_loads_as_none() {
+        let dir = TempDir::new();
+        assert!(load(dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn project_file_values_are_loaded() {
+        let dir = TempDir::new();
+        dir.write(
+            PROJECT_CONFIG_FILE,
+            r#"
+commit_format = ["<type>: <english description>", "", "Log: [short description]"]
+body_line_limit = 60
+require_chinese_body = false
+
+[templates]
+short = "<type>: <english description> Log: [short description]"
+"#,
+        );
+
+        let project = load(dir.path()).unwrap().unwrap();
+        assert_eq!(project.commit_format.unwrap().into_vec().len(), 3);
+        assert_eq!(project.body_line_limit, Some(60));
+        assert_eq!(project.require_chinese_body, Some(false));
+        assert_eq!(
+            project.templates.unwrap()["short"].clone().into_vec().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn template_without_required_markers_is_rejected() {
+        let dir = TempDir::new();
+        dir.write(
+            PROJECT_CONFIG_FILE,
+            "[templates]\nbroken = [\"<type>: <english description>\"]\n",
+        );
+
+        let err = load(dir.path()).unwrap_err().to_string();
+        assert!(err.contains("templates.broken"), "{}", err);
+        assert!(err.contains("Log:"), "{}", err);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let dir = TempDir::new();
+        dir.write(PROJECT_CONFIG_FILE, "body_limit = 60\n");
+        assert!(load(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::git::ChangedFile;
+use serde::Serialize;
+use std::path::Path;
+
+const DOC_EXTENSIONS: &[&str] = &["md", "markdown", "rst", "adoc"];
+const DOC_FILE_PREFIXES: &[&str] = &["README", "CHANGELOG", "LICENSE", "CONTRIBUTING"];
+const CONFIG_EXTENSIONS: &[&str] = &["toml", "yaml", "yml", "json", "lock", "ini", "cfg"];
+const CONFIG_FILES: &[&str] = &[
+    "Makefile",
+    "Dockerfile",
+    "build.rs",
+    ".gitignore",
+    ".gitattributes",
+    ".editorconfig",
+];
+
+/// 根据暂存文件推测出的提交信息草稿
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Draft {
+    pub commit_type: String,
+    pub scope: Option<String>,
+    pub subject: String,
+    /// 推测时依据的规则，便于模型判断是否采纳
+    pub evidence: Vec<String>,
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(path)
+}
+
+fn extension(path: &str) -> Option<&str> {
+    Path::new(path).extension().and_then(|e| e.to_str())
+}
+
+fn is_doc(path: &str) -> bool {
+    let name = file_name(path);
+    path.starts_with("docs/")
+        || path.starts_with("doc/")
+        || DOC_FILE_PREFIXES.iter().any(|p| name.starts_with(p))
+        || extension(path).is_some_and(|e| DOC_EXTENSIONS.contains(&e))
+}
+
+fn is_test(path: &str) -> bool {
+    let name = file_name(path);
+    path.split('/')
+        .any(|segment| segment == "tests" || segment == "test")
+        || name.starts_with("test_")
+        || name.contains("_test.")
+        || name.contains(".test.")
+        || name.contains("_spec.")
+}
+
+fn is_config(path: &str) -> bool {
+    path.starts_with(".github/")
+        || CONFIG_FILES.contains(&file_name(path))
+        || extension(path).is_some_and(|e| CONFIG_EXTENSIONS.contains(&e))
+}
+
+/// 按文件路径与当前分支名推测 conventional commit 的类型
+pub fn infer_type(files: &[ChangedFile], branch: Option<&str>) -> (&'static str, String) {
+    let all = |pred: fn(&str) -> bool| files.iter().all(|f| pred(&f.path));
+
+    if all(is_doc) {
+        ("docs", "所有变更文件都是文档".to_string())
+    } else if all(is_test) {
+        ("test", "所有变更文件都是测试".to_string())
+    } else if all(is_config) {
+        ("chore", "所有变更文件都是配置或构建文件".to_string())
+    } else if let Some(branch) = branch.filter(|b| {
+        let b = b.to_lowercase();
+        b.contains("fix") || b.contains("bug")
+    }) {
+        ("fix", format!("分支名 {} 表明这是一次修复", branch))
+    } else {
+        ("feat", "包含源代码变更".to_string())
+    }
+}
+
+/// 所有变更文件的最近公共目录名，位于仓库根目录时为 `None`
+pub fn infer_scope(files: &[ChangedFile]) -> Option<String> {
+    let mut dirs = files.iter().map(|f| {
+        let mut parts: Vec<&str> = f.path.split('/').collect();
+        parts.pop();
+        parts
+    });
+    let mut common = dirs.next()?;
+    for dir in dirs {
+        let shared = common
+            .iter()
+            .zip(dir.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        common.truncate(shared);
+    }
+    common.last().map(|s| s.to_string())
+}
+
+/// 由暂存文件生成提交标题草稿
+pub fn draft(files: &[ChangedFile], branch: Option<&str>) -> Draft {
+    let (commit_type, type_reason) = infer_type(files, branch);
+    let scope = infer_scope(files);
+
+    let verb = if files.iter().all(|f| f.status == "added") {
+        "add"
+    } else if files.iter().all(|f| f.status == "deleted") {
+        "remove"
+    } else if files.iter().all(|f| f.status == "renamed") {
+        "rename"
+    } else {
+        "update"
+    };
+    let target = match files {
+        [single] => file_name(&single.path).to_string(),
+        _ => format!("{} files", files.len()),
+    };
+
+    let subject = match &scope {
+        Some(scope) => format!("{}({}): {} {}", commit_type, scope, verb, target),
+        None => format!("{}: {} {}", commit_type, verb, target),
+    };
+
+    let mut evidence = vec![format!("类型 {}：{}", commit_type, type_reason)];
+    match &scope {
+        Some(scope) => evidence.push(format!("范围 {}：所有变更文件的公共目录", scope)),
+        None => evidence.push("未设置范围：变更文件没有公共目录".to_string()),
+    }
+    evidence.push(format!("动词 {}：{} 个文件的变更类型", verb, files.len()));
+
+    Draft {
+        commit_type: commit_type.to_string(),
+        scope,
+        subject,
+        evidence,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changed(entries: &[(&str, &'static str)]) -> Vec<ChangedFile> {
+        entries
+            .iter()
+            .map(|(path, status)| ChangedFile {
+                path: path.to_string(),
+                status,
+                old_path: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scope_is_the_deepest_common_directory() {
+        let files = changed(&[
+            ("src/server/http.rs", "modified"),
+            ("src/server/stdio.rs", "modified"),
+        ]);
+        assert_eq!(infer_scope(&files).as_deref(), Some("server"));
+
+        let files = changed(&[
+            ("src/server/http.rs", "modified"),
+            ("src/git.rs", "modified"),
+        ]);
+        assert_eq!(infer_scope(&files).as_deref(), Some("src"));
+
+        let files = changed(&[("src/git.rs", "modified"), ("README.md", "modified")]);
+        assert_eq!(infer_scope(&files), None);
+    }
+
+    #[test]
+    fn draft_subject_combines_type_scope_and_verb() {
+        let files = changed(&[("src/cache/lru.rs", "added")]);
+        let added = draft(&files, Some("main"));
+        assert_eq!(added.commit_type, "feat");
+        assert_eq!(added.subject, "feat(cache): add lru.rs");
+        assert_eq!(added.evidence.len(), 3);
+
+        let files = changed(&[("old.md", "deleted"), ("older.md", "deleted")]);
+        assert_eq!(draft(&files, None).subject, "docs: remove 2 files");
+
+        let files = changed(&[("src/a.rs", "added"), ("src/b.rs", "modified")]);
+        assert_eq!(draft(&files, None).subject, "feat(src): update 2 files");
+    }
+
+    #[test]
+    fn type_is_inferred_from_file_sets_and_branch() {
+        let cases: &[(&[&str], Option<&str>, &str)] = &[
+            (&["README.md", "docs/usage.md"], Some("fix/typo"), "docs"),
+            (&["tests/cli.rs", "src/parser_test.go"], None, "test"),
+            (
+                &["Cargo.toml", ".github/workflows/ci.yml", "build.rs"],
+                None,
+                "chore",
+            ),
+            (&["src/git.rs"], Some("bugfix/index-lock"), "fix"),
+            (&["src/git.rs", "README.md"], Some("feature/stash"), "feat"),
+            (&["src/git.rs"], None, "feat"),
+        ];
+        for (paths, branch, expected) in cases {
+            let files = changed(
+                &paths
+                    .iter()
+                    .map(|path| (*path, "modified"))
+                    .collect::<Vec<_>>(),
+            );
+            assert_eq!(infer_type(&files, *branch).0, *expected, "{:?}", paths);
+        }
+    }
+}
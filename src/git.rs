@@ -1,57 +1,2911 @@
+use crate::cancel;
+use crate::draft::{self, Draft};
+use crate::message;
+use crate::word_diff;
 use anyhow::{anyhow, Result};
-use git2::{DiffFormat, DiffOptions, Repository};
+use git2::{
+    BlameOptions, BranchType, Config, ConfigLevel, Cred, CredentialType, Delta, Diff, DiffDelta,
+    DiffFindOptions, DiffLineType, DiffOptions, ErrorCode, FileMode, Index, IndexAddOption, Oid,
+    Patch, PushOptions, RemoteCallbacks, Repository, RepositoryOpenFlags, RepositoryState,
+    ResetType, Signature, Time,
+};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::Duration;
 
-pub struct GitHandler;
+lazy_static::lazy_static! {
+    static ref PMS_PATTERN: Regex = Regex::new(r"(?i)\b(BUG|TASK)-\d+").unwrap();
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileStat {
+    pub path: String,
+    /// 重命名或复制前的路径
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffStat {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub files: Vec<FileStat>,
+}
+
+impl DiffStat {
+    /// 类似 `git diff --stat` 的文本：总计一行，随后每个文件一行
+    pub fn summary(&self) -> String {
+        let per_file = self
+            .files
+            .iter()
+            .map(|f| match &f.old_path {
+                Some(old_path) => format!(
+                    "{} => {} | +{} -{}",
+                    old_path, f.path, f.insertions, f.deletions
+                ),
+                None => format!("{} | +{} -{}", f.path, f.insertions, f.deletions),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            "{} files changed, {} insertions(+), {} deletions(-)\n\n{}",
+            self.files_changed, self.insertions, self.deletions, per_file
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BranchInfo {
+    /// 分支名；处于分离 HEAD 状态时为缩写的提交 SHA
+    pub branch: String,
+    pub detached: bool,
+}
+
+/// 推送的结果：实际使用的远程仓库与分支
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushResult {
+    pub remote: String,
+    /// 推送的本地分支
+    pub branch: String,
+    /// 推送到的远程分支名
+    pub remote_branch: String,
+    /// 远程仓库的来源：参数指定、上游配置或默认的 origin
+    pub source: &'static str,
+}
+
+/// `branches` 列出的一个分支
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BranchEntry {
+    pub name: String,
+    /// 是否为远程跟踪分支（如 `origin/main`）
+    pub remote: bool,
+    /// 是否为当前所在的分支
+    pub current: bool,
+    /// 本地分支跟踪的上游分支，未设置时为 `None`
+    pub upstream: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogEntry {
+    pub id: String,
+    pub subject: String,
+    pub author: String,
+    /// 作者时间，格式由 `DateFormat` 决定
+    pub date: String,
+}
+
+/// HEAD 指向的提交
+#[derive(Debug, Serialize)]
+pub struct LastCommit {
+    pub id: String,
+    pub author: String,
+    pub email: String,
+    /// 作者时间，格式由 `DateFormat` 决定，默认为保留原时区的 RFC 3339
+    pub date: String,
+    pub message: String,
+}
+
+/// `verify_signature` 的结果
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureCheck {
+    pub commit: String,
+    pub signed: bool,
+    /// 签名格式：`openpgp`、`x509` 或 `ssh`
+    pub format: Option<&'static str>,
+    /// 签名者身份：GPG 为密钥的用户 ID（找不到公钥时为密钥 ID），SSH 为 allowed signers 中的主体
+    pub signer: Option<String>,
+    /// 签名已用本机配置的密钥验证通过
+    pub valid: bool,
+    /// 验证工具的输出或无法验证的原因
+    pub detail: Option<String>,
+}
+
+/// 一段连续的行最后由同一提交修改
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlameHunk {
+    pub id: String,
+    pub author: String,
+    pub summary: String,
+    /// 该段在当前文件中的起始行号（从 1 开始）
+    pub start_line: usize,
+    pub lines: usize,
+}
+
+/// 新建提交的信息
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitResult {
+    pub id: String,
+    pub short_id: String,
+    /// 提交所在的分支；分离 HEAD 时为缩写的提交 SHA
+    pub branch: String,
+    /// 在分离 HEAD 状态下提交，新提交不属于任何分支
+    pub detached: bool,
+    /// 提交信息的第一行
+    pub subject: String,
+}
+
+/// `revert_commit` 的结果
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevertResult {
+    /// 被撤销的提交
+    pub reverted: String,
+    /// 撤销提交的提交信息
+    pub message: String,
+    /// 撤销涉及的文件
+    pub files: Vec<String>,
+    /// 创建的撤销提交，仅暂存时为 `None`
+    pub commit: Option<CommitResult>,
+}
+
+/// `preview_commit` 的结果：将要创建的提交的内容
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitPreview {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub branch: String,
+    /// 作者与提交者身份，格式为 `Name <email>`
+    pub author: String,
+    /// 追加 trailer 后的最终提交信息
+    pub message: String,
+}
+
+/// 创建提交前确定的内容
+struct PreparedCommit<'r> {
+    message: String,
+    sig: Signature<'static>,
+    parents: Vec<git2::Commit<'r>>,
+}
+
+/// 树中某个路径的新内容：对象 id 与文件模式，`None` 表示删除该路径
+type TreeUpdate = (String, Option<(Oid, i32)>);
+
+/// 暂存区中的一个变更文件
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangedFile {
+    pub path: String,
+    /// added、modified、deleted、renamed、copied 或 typechange
+    pub status: &'static str,
+    /// 重命名或复制前的路径
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
+}
+
+/// `get_staged_diff` 工具返回的暂存区差异及从中推测的信息，均基于同一份差异
+#[derive(Debug)]
+pub struct StagedReview {
+    pub diff: String,
+    /// 暂存的变更文件，包含未跟踪文件时以 `untracked` 状态追加在末尾
+    pub files: Vec<ChangedFile>,
+    /// 只由暂存的文件推测，没有暂存文件时为 `None`
+    pub suggested_type: Option<&'static str>,
+    pub suggested_pms: Option<String>,
+}
+
+/// 工作区状态概览，同一文件可能同时出现在 `staged` 与 `modified` 中
+#[derive(Debug, Default, Serialize)]
+pub struct StatusSummary {
+    pub staged: Vec<String>,
+    pub modified: Vec<String>,
+    pub untracked: Vec<String>,
+    pub conflicted: Vec<String>,
+}
+
+/// `is_clean` 的结果
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CleanStatus {
+    pub clean: bool,
+    /// 有变更的路径总数，未跟踪的目录计为一个
+    pub dirty_count: usize,
+    /// 前几个有变更的路径，便于说明原因
+    pub dirty: Vec<String>,
+}
+
+/// `is_clean` 最多列出的变更路径数
+const MAX_DIRTY_PATHS: usize = 10;
+
+/// 控制差异输出的选项
+#[derive(Debug, Clone)]
+pub struct DiffSettings {
+    /// 每个 hunk 前后保留的上下文行数，0 表示只输出变更行
+    pub context_lines: u32,
+    /// 忽略空白字符（包括行尾空白）的差异
+    pub ignore_whitespace: bool,
+    /// 输出 patch 的最大字节数，超出部分在文件边界处截断，0 表示不限制
+    pub max_bytes: usize,
+    /// 将未跟踪的文件（遵循 `.gitignore`）当作新增文件一并输出
+    pub include_untracked: bool,
+    /// 变更文件数超过该值时不输出 patch，只返回统计信息，0 表示不限制
+    pub max_files: usize,
+    /// 以 `[-删除-]{+新增+}` 的词级标记代替行级的 `-`/`+` 输出
+    pub word_diff: bool,
+    /// 识别重命名的相似度阈值（0–100），未指定时使用 git 的默认值 50
+    pub rename_threshold: Option<u16>,
+    /// 从暂存区差异中省略的文件（锁文件、压缩产物等）的 glob 模式
+    pub exclude_patterns: Vec<String>,
+    /// 差异的输出格式
+    pub format: DiffFormat,
+    /// 按行把 patch 拆分为多个不超过该字节数的内容块，避免单个内容块过大，0 表示不拆分
+    pub chunk_bytes: usize,
+}
+
+/// 差异的输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DiffFormat {
+    /// unified diff 文本
+    #[default]
+    Patch,
+    /// 按文件列出结构化的 hunk，便于客户端自行渲染；不支持词级标记
+    Json,
+}
+
+impl Default for DiffSettings {
+    fn default() -> Self {
+        DiffSettings {
+            context_lines: 3,
+            ignore_whitespace: false,
+            max_bytes: 100_000,
+            include_untracked: false,
+            max_files: 200,
+            word_diff: false,
+            rename_threshold: None,
+            exclude_patterns: DEFAULT_EXCLUDE_PATTERNS
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            format: DiffFormat::Patch,
+            chunk_bytes: 0,
+        }
+    }
+}
+
+/// 默认从暂存区差异中省略的生成文件与锁文件
+pub const DEFAULT_EXCLUDE_PATTERNS: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "poetry.lock",
+    "go.sum",
+    "*.min.js",
+    "*.min.css",
+    "*.map",
+];
+
+/// 简单的 glob 匹配：`*` 匹配除 `/` 外的任意字符，`**` 可跨目录，`?` 匹配单个字符；
+/// 不含 `/` 的模式只与文件名比较
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern {
+            [] => text.is_empty(),
+            ['*', '*', rest @ ..] => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+            ['*', rest @ ..] => (0..=text.len())
+                .take_while(|&i| i == 0 || text[i - 1] != '/')
+                .any(|i| matches(rest, &text[i..])),
+            ['?', rest @ ..] => {
+                text.first().is_some_and(|c| *c != '/') && matches(rest, &text[1..])
+            }
+            [c, rest @ ..] => text.first() == Some(c) && matches(rest, &text[1..]),
+        }
+    }
+
+    let target = if pattern.contains('/') {
+        path
+    } else {
+        path.rsplit('/').next().unwrap_or(path)
+    };
+    let pattern: Vec<char> = pattern.chars().collect();
+    let target: Vec<char> = target.chars().collect();
+    matches(&pattern, &target)
+}
+
+/// 渲染差异时要跳过的文件与字节预算：累计输出超过 `max_bytes` 后不再渲染后续文件，只记录其路径，
+/// 使截断同时限制了大型差异的内存与耗时
+#[derive(Debug, Default)]
+struct RenderScope {
+    /// 被 `exclude_patterns` 省略、不渲染的文件
+    excluded: Vec<String>,
+    /// 0 表示不限制
+    max_bytes: usize,
+    rendered_bytes: usize,
+    /// 预算用完后未渲染的文件
+    unrendered: Vec<String>,
+}
+
+impl RenderScope {
+    fn within(max_bytes: usize) -> Self {
+        RenderScope {
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.max_bytes > 0 && self.rendered_bytes > self.max_bytes
+    }
+}
+
+/// 截断说明中最多列出的未渲染文件数
+const MAX_UNRENDERED_LISTED: usize = 20;
+
+/// 单个文件的 patch 文本；JSON 格式下同时给出结构化的 hunk
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilePatch {
+    pub path: String,
+    #[serde(skip)]
+    pub text: String,
+    pub binary: bool,
+    pub hunks: Vec<PatchHunk>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submodule: Option<SubmoduleUpdate>,
+}
+
+/// 子模块记录的提交的变化；新增或删除子模块时对应一侧为 `None`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmoduleUpdate {
+    pub old_commit: Option<String>,
+    pub new_commit: Option<String>,
+    /// 两个提交之间的子模块提交标题（从新到旧），子模块未检出或缺少对象时为空
+    pub commits: Vec<String>,
+}
+
+impl SubmoduleUpdate {
+    /// 类似 `git diff --submodule=log` 的说明，追加在 `Subproject commit` 差异之后
+    fn summary(&self, path: &str) -> String {
+        let short = |id: &String| id[..7.min(id.len())].to_string();
+        let mut text = match (&self.old_commit, &self.new_commit) {
+            (None, Some(new)) => format!("Submodule {} {} (new submodule)\n", path, short(new)),
+            (Some(old), None) => format!("Submodule {} {} (submodule deleted)\n", path, short(old)),
+            (Some(old), Some(new)) if self.commits.is_empty() => {
+                format!("Submodule {} {}..{}\n", path, short(old), short(new))
+            }
+            (Some(old), Some(new)) => {
+                format!("Submodule {} {}..{}:\n", path, short(old), short(new))
+            }
+            (None, None) => String::new(),
+        };
+        for subject in &self.commits {
+            text.push_str(&format!("  > {}\n", subject));
+        }
+        text
+    }
+}
+
+/// JSON 格式差异中的一个 hunk
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchHunk {
+    pub old_start: u32,
+    pub old_lines: u32,
+    pub new_start: u32,
+    pub new_lines: u32,
+    pub lines: Vec<PatchLine>,
+}
+
+/// hunk 中的一行：`origin` 为 ` `、`+`、`-`，或表示文件末尾缺少换行的 `=`、`>`、`<`
+#[derive(Debug, Serialize)]
+pub struct PatchLine {
+    pub origin: String,
+    /// 行内容，不含行尾换行符
+    pub content: String,
+}
+
+#[derive(Debug, Default)]
+pub struct CommitOptions {
+    /// 是否签名提交，未指定时沿用 git 配置 `commit.gpgsign`
+    pub sign: Option<bool>,
+    /// 签名密钥，未指定时读取 git 配置 `user.signingkey`
+    pub signing_key: Option<String>,
+    /// 以 `Co-authored-by:` 追加的合作者，格式为 `Name <email>`
+    pub co_authors: Vec<String>,
+    /// 跳过 pre-commit 钩子
+    pub skip_hooks: bool,
+    /// 允许创建与父提交树相同的空提交
+    pub allow_empty: bool,
+    /// 覆盖作者与提交者身份 `(name, email)`，未指定时使用 git 配置中的身份
+    pub author: Option<(String, String)>,
+    /// 覆盖作者与提交者时间，未指定时使用当前时间
+    pub date: Option<Time>,
+    /// 提交前检查标题的长度与格式，不符合时拒绝提交
+    pub strict: bool,
+    /// 严格模式下要求标题为 `<type>[scope]: description` 格式
+    pub require_type: bool,
+    /// 以提交者身份追加 `Signed-off-by:` trailer，同 `git commit -s`
+    pub signoff: bool,
+}
+
+/// 解析提交时间：RFC 3339（如 `2024-05-01T12:00:00+08:00`）或 Unix 时间戳（秒，UTC）
+pub fn parse_commit_date(value: &str) -> Result<Time> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<i64>() {
+        return Ok(Time::new(secs, 0));
+    }
+    let date = chrono::DateTime::parse_from_rfc3339(value).map_err(|e| {
+        GitError::InvalidArgument.with(format!(
+            "无效的提交时间 `{}`：{}（应为 RFC 3339 格式或 Unix 时间戳）",
+            value, e
+        ))
+    })?;
+    Ok(Time::new(
+        date.timestamp(),
+        date.offset().local_minus_utc() / 60,
+    ))
+}
+
+/// 提交时间的输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DateFormat {
+    /// RFC 3339，保留提交时的时区
+    #[default]
+    Iso,
+    /// 类似 `git log --date=relative` 的相对时间，如 `3 days ago`
+    Relative,
+    /// Unix 时间戳（秒）
+    Unix,
+}
+
+impl DateFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "iso" => Some(DateFormat::Iso),
+            "relative" => Some(DateFormat::Relative),
+            "unix" => Some(DateFormat::Unix),
+            _ => None,
+        }
+    }
+}
+
+/// 按给定格式输出提交时间，RFC 3339 格式使用提交时记录的时区
+pub fn format_commit_time(time: Time, format: DateFormat) -> String {
+    match format {
+        DateFormat::Iso => chrono::FixedOffset::east_opt(time.offset_minutes() * 60)
+            .and_then(|offset| {
+                chrono::DateTime::from_timestamp(time.seconds(), 0)
+                    .map(|d| d.with_timezone(&offset))
+            })
+            .map(|d| d.to_rfc3339())
+            .unwrap_or_else(|| time.seconds().to_string()),
+        DateFormat::Relative => relative_time(chrono::Utc::now().timestamp() - time.seconds()),
+        DateFormat::Unix => time.seconds().to_string(),
+    }
+}
+
+/// 与 git 的 `--date=relative` 采用相同的分段
+fn relative_time(diff: i64) -> String {
+    fn ago(n: i64, unit: &str) -> String {
+        format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" })
+    }
+
+    if diff < 0 {
+        return "in the future".to_string();
+    }
+    if diff < 90 {
+        return ago(diff, "second");
+    }
+    let minutes = (diff + 30) / 60;
+    if minutes < 90 {
+        return ago(minutes, "minute");
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return ago(hours, "hour");
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return ago(days, "day");
+    }
+    if days < 70 {
+        return ago((days + 3) / 7, "week");
+    }
+    if days < 365 {
+        return ago((days + 15) / 30, "month");
+    }
+    let years = days / 365;
+    let months = (days % 365 * 12 + 182) / 365;
+    if years < 5 && months > 0 {
+        let year_part = format!("{} year{}", years, if years == 1 { "" } else { "s" });
+        return format!("{}, {}", year_part, ago(months, "month"));
+    }
+    ago((days + 183) / 365, "year")
+}
+
+/// 可供客户端程序化处理的错误类别，随工具结果的 `code` 字段返回
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GitError {
+    NotARepo,
+    NoStagedChanges,
+    NoUnstagedChanges,
+    NoCommits,
+    NothingToCommit,
+    MergeConflict,
+    HookFailed,
+    InvalidMessage,
+    TooManyFiles,
+    RevisionNotFound,
+    PathNotFound,
+    PushRejected,
+    AuthFailed,
+    IndexLocked,
+    Cancelled,
+    InvalidArgument,
+    UnknownTool,
+    AlreadyExists,
+    NothingToStash,
+    EmptyStash,
+    NoDiff,
+    RootCommit,
+}
+
+impl GitError {
+    pub fn code(self) -> &'static str {
+        match self {
+            GitError::NotARepo => "NOT_A_REPO",
+            GitError::NoStagedChanges => "NO_STAGED_CHANGES",
+            GitError::NoUnstagedChanges => "NO_UNSTAGED_CHANGES",
+            GitError::NoCommits => "NO_COMMITS",
+            GitError::NothingToCommit => "NOTHING_TO_COMMIT",
+            GitError::MergeConflict => "MERGE_CONFLICT",
+            GitError::HookFailed => "HOOK_FAILED",
+            GitError::InvalidMessage => "INVALID_MESSAGE",
+            GitError::TooManyFiles => "TOO_MANY_FILES",
+            GitError::RevisionNotFound => "REVISION_NOT_FOUND",
+            GitError::PathNotFound => "PATH_NOT_FOUND",
+            GitError::PushRejected => "PUSH_REJECTED",
+            GitError::AuthFailed => "AUTH_FAILED",
+            GitError::IndexLocked => "INDEX_LOCKED",
+            GitError::Cancelled => "CANCELLED",
+            GitError::InvalidArgument => "INVALID_ARGUMENT",
+            GitError::UnknownTool => "UNKNOWN_TOOL",
+            GitError::AlreadyExists => "ALREADY_EXISTS",
+            GitError::NothingToStash => "NOTHING_TO_STASH",
+            GitError::EmptyStash => "EMPTY_STASH",
+            GitError::NoDiff => "NO_DIFF",
+            GitError::RootCommit => "ROOT_COMMIT",
+        }
+    }
+
+    /// 生成带有该错误类别的错误
+    pub fn with(self, message: impl Into<String>) -> anyhow::Error {
+        anyhow::Error::new(CodedError {
+            kind: self,
+            message: message.into(),
+        })
+    }
+
+    /// 取出错误的类别，未分类的错误返回 `None`
+    pub fn of(error: &anyhow::Error) -> Option<GitError> {
+        error.downcast_ref::<CodedError>().map(|e| e.kind)
+    }
+}
+
+#[derive(Debug)]
+struct CodedError {
+    kind: GitError,
+    message: String,
+}
+
+impl std::fmt::Display for CodedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+/// `config_get` 与 `config_set` 允许访问的 git 配置项
+pub const ALLOWED_CONFIG_KEYS: &[&str] = &[
+    "user.name",
+    "user.email",
+    "user.signingkey",
+    "commit.gpgsign",
+    "tag.gpgsign",
+    "gpg.format",
+];
+
+/// 取值为布尔值的配置项，写入前校验
+const BOOL_CONFIG_KEYS: &[&str] = &["commit.gpgsign", "tag.gpgsign"];
+
+/// 一个 git 配置项的值
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigEntry {
+    pub key: String,
+    /// 生效的值，未设置时为 `None`
+    pub value: Option<String>,
+    /// 值所在的配置级别：`local`、`global`、`system` 等
+    pub scope: Option<&'static str>,
+}
+
+/// 持有一个打开的仓库句柄，避免每次工具调用都重新打开仓库、读取配置和对象库
+pub struct GitHandler {
+    repo: Repository,
+    /// 索引被其他 git 进程锁定时的重试次数
+    index_lock_retries: u32,
+}
+
+/// 索引被锁定时默认的重试次数
+pub const DEFAULT_INDEX_LOCK_RETRIES: u32 = 3;
+/// 第一次重试前的等待时间，此后每次递增相同的时长
+const INDEX_LOCK_BACKOFF: Duration = Duration::from_millis(100);
+
+impl GitHandler {
+    /// 从给定路径向上查找并打开所在的仓库，支持子目录与链接工作树；
+    /// 与 git 一致，只有设置 `GIT_DISCOVERY_ACROSS_FILESYSTEM` 时才跨越文件系统边界查找
+    pub fn open(repo_path: &str) -> Result<Self> {
+        let mut flags = RepositoryOpenFlags::empty();
+        if std::env::var("GIT_DISCOVERY_ACROSS_FILESYSTEM")
+            .is_ok_and(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+        {
+            flags |= RepositoryOpenFlags::CROSS_FS;
+        }
+        let repo = match Repository::open_ext(repo_path, flags, &[] as &[&OsStr]) {
+            Ok(repo) => repo,
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                let path =
+                    std::fs::canonicalize(repo_path).unwrap_or_else(|_| PathBuf::from(repo_path));
+                return Err(
+                    GitError::NotARepo.with(format!("当前目录不是 git 仓库: {}", path.display()))
+                );
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(GitHandler {
+            repo,
+            index_lock_retries: DEFAULT_INDEX_LOCK_RETRIES,
+        })
+    }
+
+    /// 是否设置了 `GIT_DIR` 或 `GIT_WORK_TREE` 环境变量
+    pub fn env_configured() -> bool {
+        ["GIT_DIR", "GIT_WORK_TREE"]
+            .iter()
+            .any(|var| std::env::var_os(var).is_some_and(|v| !v.is_empty()))
+    }
+
+    /// 按 `GIT_DIR` 与 `GIT_WORK_TREE` 环境变量打开仓库，适用于 CI 等不以当前目录定位仓库的环境；
+    /// 未设置 `GIT_DIR` 时从当前目录向上查找
+    pub fn open_from_env() -> Result<Self> {
+        let handler = match std::env::var_os("GIT_DIR").filter(|v| !v.is_empty()) {
+            Some(git_dir) => match Repository::open(&git_dir) {
+                Ok(repo) => GitHandler {
+                    repo,
+                    index_lock_retries: DEFAULT_INDEX_LOCK_RETRIES,
+                },
+                Err(e) if e.code() == ErrorCode::NotFound => {
+                    return Err(GitError::NotARepo.with(format!(
+                        "GIT_DIR 指向的不是 git 仓库: {}",
+                        PathBuf::from(git_dir).display()
+                    )));
+                }
+                Err(e) => return Err(e.into()),
+            },
+            None => Self::open(".")?,
+        };
+        // libgit2 的 `open_from_env` 不支持 GIT_WORK_TREE，这里手动设置工作区
+        if let Some(work_tree) = std::env::var_os("GIT_WORK_TREE").filter(|v| !v.is_empty()) {
+            handler.repo.set_workdir(Path::new(&work_tree), false)?;
+        }
+        Ok(handler)
+    }
+
+    pub fn set_index_lock_retries(&mut self, retries: u32) {
+        self.index_lock_retries = retries;
+    }
+
+    /// 另一个 git 进程持有 `index.lock` 或引用的锁文件时，按递增的间隔重试写入索引或更新引用的操作，
+    /// 仍被锁定时报告明确的错误
+    fn retry_locked<T>(&self, mut op: impl FnMut() -> Result<T, git2::Error>) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Err(e) if e.code() == ErrorCode::Locked => {
+                    if attempt >= self.index_lock_retries {
+                        // 引用的锁错误会给出锁文件路径，索引的锁错误则没有
+                        let detail = e.message().trim_end_matches([':', ' ']);
+                        let detail = if detail.contains(".lock") {
+                            detail.to_string()
+                        } else {
+                            format!(
+                                "{}: {}",
+                                self.repo.path().join("index.lock").display(),
+                                detail
+                            )
+                        };
+                        return Err(GitError::IndexLocked.with(format!(
+                            "索引或引用正被另一个 git 进程锁定（{}），请等待该进程结束后重试；如果没有正在运行的 git 进程，可删除残留的锁文件。",
+                            detail
+                        )));
+                    }
+                    attempt += 1;
+                    std::thread::sleep(INDEX_LOCK_BACKOFF * attempt);
+                }
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    /// 工作区根目录，裸仓库返回 `None`
+    pub fn workdir(&self) -> Option<&Path> {
+        self.repo.workdir()
+    }
+
+    /// 仓库的 `.git` 目录
+    pub fn git_dir(&self) -> &Path {
+        self.repo.path()
+    }
+
+    /// 读取 git 配置 `commit.template` 指向的提交模板，按行返回；未配置时为 `None`
+    pub fn commit_template(&self) -> Result<Option<Vec<String>>> {
+        let path = match self.repo.config()?.get_path("commit.template") {
+            Ok(path) => path,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        // 与 git 一致，相对路径相对于工作区根目录
+        let path = match self.repo.workdir() {
+            Some(root) if path.is_relative() => root.join(path),
+            _ => path,
+        };
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("无法读取提交模板 {}: {}", path.display(), e))?;
+
+        let mut lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+        while lines.last().is_some_and(|l| l.trim().is_empty()) {
+            lines.pop();
+        }
+        if lines.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(lines))
+    }
+
+    /// 读取允许访问的 git 配置项，返回生效的值及其所在的配置级别；未设置时值为 `None`
+    pub fn config_get(&self, key: &str) -> Result<ConfigEntry> {
+        let key = Self::allowed_config_key(key)?;
+        let config = self.repo.config()?;
+        let entry = match config.get_entry(key) {
+            Ok(entry) => entry,
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                return Ok(ConfigEntry {
+                    key: key.to_string(),
+                    value: None,
+                    scope: None,
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let scope = match entry.level() {
+            ConfigLevel::ProgramData | ConfigLevel::System => "system",
+            ConfigLevel::XDG | ConfigLevel::Global => "global",
+            ConfigLevel::Local => "local",
+            ConfigLevel::App | ConfigLevel::Highest => "app",
+        };
+        Ok(ConfigEntry {
+            key: key.to_string(),
+            value: entry.value().map(|v| v.to_string()),
+            scope: Some(scope),
+        })
+    }
+
+    /// 写入允许访问的 git 配置项：默认写入仓库的 `.git/config`，`global` 时写入用户的全局配置；
+    /// 返回写入的值，它可能被更高优先级的配置覆盖
+    pub fn config_set(&self, key: &str, value: &str, global: bool) -> Result<ConfigEntry> {
+        let key = Self::allowed_config_key(key)?;
+        let mut config = if global {
+            match Config::open_default()?.open_level(ConfigLevel::Global) {
+                Ok(config) => config,
+                // 还没有全局配置文件时新建 `~/.gitconfig`
+                Err(e) if e.code() == ErrorCode::NotFound => {
+                    let home = std::env::var_os("HOME")
+                        .ok_or_else(|| anyhow!("未设置 HOME，无法确定全局配置文件的位置。"))?;
+                    Config::open(&Path::new(&home).join(".gitconfig"))?
+                }
+                Err(e) => return Err(e.into()),
+            }
+        } else {
+            self.repo.config()?.open_level(ConfigLevel::Local)?
+        };
+
+        let value = if BOOL_CONFIG_KEYS.contains(&key) {
+            let flag = match value.to_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => true,
+                "false" | "no" | "off" | "0" => false,
+                _ => {
+                    return Err(GitError::InvalidArgument
+                        .with(format!("{} 的值必须为 true 或 false。", key)))
+                }
+            };
+            config.set_bool(key, flag)?;
+            flag.to_string()
+        } else {
+            config.set_str(key, value)?;
+            value.to_string()
+        };
+        Ok(ConfigEntry {
+            key: key.to_string(),
+            value: Some(value),
+            scope: Some(if global { "global" } else { "local" }),
+        })
+    }
+
+    /// 只允许访问 `ALLOWED_CONFIG_KEYS` 中的配置项，避免修改任意配置；键名不区分大小写
+    fn allowed_config_key(key: &str) -> Result<&'static str> {
+        ALLOWED_CONFIG_KEYS
+            .iter()
+            .find(|k| k.eq_ignore_ascii_case(key))
+            .copied()
+            .ok_or_else(|| {
+                GitError::InvalidArgument.with(format!(
+                    "不允许访问配置项 {}，仅支持：{}",
+                    key,
+                    ALLOWED_CONFIG_KEYS.join(", ")
+                ))
+            })
+    }
+
+    /// 获取索引，并在磁盘上的索引被其他进程修改过时重新读取
+    fn index(&self) -> Result<Index> {
+        let mut index = self.repo.index()?;
+        index.read(false)?;
+        Ok(index)
+    }
+
+    /// HEAD 与索引之间的差异，即 `git diff --staged`
+    fn staged_diff<'r>(repo: &'r Repository, settings: &DiffSettings) -> Result<Diff<'r>> {
+        Self::staged_diff_with(repo, Self::diff_options(settings), settings)
+    }
+
+    fn staged_diff_with<'r>(
+        repo: &'r Repository,
+        mut opts: DiffOptions,
+        settings: &DiffSettings,
+    ) -> Result<Diff<'r>> {
+        // 尝试获取 HEAD 树，如果不存在（如新仓库），则使用空树
+        let head_tree = repo.head().and_then(|h| h.peel_to_tree()).ok();
+
+        let mut diff = repo.diff_tree_to_index(head_tree.as_ref(), None, Some(&mut opts))?;
+
+        // 识别重命名和复制，避免被显示为删除 + 新增
+        diff.find_similar(Some(&mut Self::find_options(settings)))?;
+
+        Ok(diff)
+    }
+
+    fn diff_options(settings: &DiffSettings) -> DiffOptions {
+        let mut opts = DiffOptions::new();
+        opts.context_lines(settings.context_lines);
+        if settings.ignore_whitespace {
+            opts.ignore_whitespace(true).ignore_whitespace_eol(true);
+        }
+        opts
+    }
+
+    /// 重命名与复制检测的选项；阈值只作用于重命名，复制检测保持 git 默认的 50%
+    fn find_options(settings: &DiffSettings) -> DiffFindOptions {
+        let mut find_opts = DiffFindOptions::new();
+        find_opts.renames(true).copies(true);
+        if let Some(threshold) = settings.rename_threshold {
+            find_opts.rename_threshold(threshold);
+        }
+        find_opts
+    }
+
+    fn has_changes(&self, diff: Diff) -> Result<bool> {
+        Ok(!self
+            .render_files(&diff, &DiffSettings::default(), &mut RenderScope::default())?
+            .is_empty())
+    }
+
+    /// 未跟踪文件相对索引的差异，以新增文件的形式包含其完整内容；没有未跟踪文件时为 `None`
+    fn untracked_diff(&self, settings: &DiffSettings) -> Result<Option<Diff<'_>>> {
+        let untracked = self.untracked_files()?;
+        if untracked.is_empty() {
+            return Ok(None);
+        }
+
+        let mut opts = Self::diff_options(settings);
+        opts.include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true)
+            .disable_pathspec_match(true);
+        for path in &untracked {
+            opts.pathspec(path);
+        }
+        let diff = self
+            .repo
+            .diff_index_to_workdir(Some(&self.index()?), Some(&mut opts))?;
+        Ok(Some(diff))
+    }
+
+    /// 逐个文件以 patch 格式输出差异，二进制文件只输出一行大小变化说明；
+    /// 跳过 `scope` 中省略的文件，预算用完后的文件只记录路径
+    fn render_files(
+        &self,
+        diff: &Diff,
+        settings: &DiffSettings,
+        scope: &mut RenderScope,
+    ) -> Result<Vec<FilePatch>> {
+        let mut files = Vec::new();
+        for idx in 0..diff.deltas().len() {
+            if cancel::is_cancelled() {
+                return Err(GitError::Cancelled.with("操作已被客户端取消。"));
+            }
+            if let Some(delta) = diff.get_delta(idx) {
+                let path = Self::delta_path(&delta);
+                if scope.excluded.contains(&path) {
+                    continue;
+                }
+                if scope.exhausted() {
+                    scope.unrendered.push(path);
+                    continue;
+                }
+            }
+
+            let Some(mut patch) = Patch::from_diff(diff, idx)? else {
+                continue;
+            };
+
+            // 忽略空白等规则生效后内容已无差异的文件，libgit2 仍会输出文件头，这里跳过
+            let delta = patch.delta();
+            if patch.num_hunks() == 0
+                && delta.status() == Delta::Modified
+                && !delta.flags().is_binary()
+                && delta.old_file().mode() == delta.new_file().mode()
+            {
+                continue;
+            }
+
+            let path = Self::delta_path(&delta);
+            let mut diff_text = Vec::new();
+            patch.print(&mut |delta, _hunk, line| {
+                match line.origin_value() {
+                    DiffLineType::Binary => {
+                        let placeholder = format!(
+                            "Binary file {} changed ({} -> {} bytes)\n",
+                            Self::delta_path(&delta),
+                            delta.old_file().size(),
+                            delta.new_file().size()
+                        );
+                        diff_text.extend_from_slice(placeholder.as_bytes());
+                    }
+                    DiffLineType::Context | DiffLineType::Addition | DiffLineType::Deletion => {
+                        diff_text.push(line.origin() as u8);
+                        diff_text.extend_from_slice(line.content());
+                    }
+                    _ => diff_text.extend_from_slice(line.content()),
+                }
+                // 返回 false 会中止输出，用于及时响应取消
+                !cancel::is_cancelled()
+            })?;
+
+            let text = String::from_utf8_lossy(&diff_text).to_string();
+            let mut text = if settings.word_diff && settings.format == DiffFormat::Patch {
+                word_diff::render(&text)
+            } else {
+                text
+            };
+            let hunks = match settings.format {
+                DiffFormat::Json => Self::patch_hunks(&patch)?,
+                DiffFormat::Patch => Vec::new(),
+            };
+            let submodule = self.submodule_update(&delta, &path);
+            if let Some(update) = &submodule {
+                text.push_str(&update.summary(&path));
+            }
+            scope.rendered_bytes += text.len();
+            files.push(FilePatch {
+                path,
+                text,
+                binary: delta.flags().is_binary(),
+                hunks,
+                submodule,
+            });
+        }
+
+        Ok(files)
+    }
+
+    /// 子模块（gitlink）条目记录的提交的变化；子模块已检出时一并列出两个提交之间的提交标题
+    fn submodule_update(&self, delta: &DiffDelta, path: &str) -> Option<SubmoduleUpdate> {
+        let (old, new) = (delta.old_file(), delta.new_file());
+        let is_gitlink = |file: &git2::DiffFile| file.mode() == FileMode::Commit;
+        if !is_gitlink(&old) && !is_gitlink(&new) {
+            return None;
+        }
+
+        let old_commit = Some(old.id()).filter(|id| is_gitlink(&old) && !id.is_zero());
+        let new_commit = Some(new.id()).filter(|id| is_gitlink(&new) && !id.is_zero());
+        let commits = match (old_commit, new_commit) {
+            (Some(old_id), Some(new_id)) => {
+                Self::submodule_log(&self.repo, path, old_id, new_id).unwrap_or_default()
+            }
+            _ => Vec::new(),
+        };
+        Some(SubmoduleUpdate {
+            old_commit: old_commit.map(|id| id.to_string()),
+            new_commit: new_commit.map(|id| id.to_string()),
+            commits,
+        })
+    }
+
+    fn submodule_log(repo: &Repository, path: &str, old: Oid, new: Oid) -> Result<Vec<String>> {
+        const MAX_SUBMODULE_COMMITS: usize = 20;
+        let sub_repo = repo.find_submodule(path)?.open()?;
+        let mut walk = sub_repo.revwalk()?;
+        walk.push(new)?;
+        walk.hide(old)?;
+        let mut subjects = Vec::new();
+        for id in walk.take(MAX_SUBMODULE_COMMITS) {
+            let commit = sub_repo.find_commit(id?)?;
+            subjects.push(commit.summary().unwrap_or_default().to_string());
+        }
+        Ok(subjects)
+    }
+
+    fn patch_hunks(patch: &Patch) -> Result<Vec<PatchHunk>> {
+        let mut hunks = Vec::with_capacity(patch.num_hunks());
+        for hunk_idx in 0..patch.num_hunks() {
+            let (hunk, line_count) = patch.hunk(hunk_idx)?;
+            let mut lines = Vec::with_capacity(line_count);
+            for line_idx in 0..line_count {
+                let line = patch.line_in_hunk(hunk_idx, line_idx)?;
+                let content = String::from_utf8_lossy(line.content());
+                lines.push(PatchLine {
+                    origin: line.origin().to_string(),
+                    // 文件末尾缺少换行的说明行以换行符开头
+                    content: content.trim_matches('\n').to_string(),
+                });
+            }
+            hunks.push(PatchHunk {
+                old_start: hunk.old_start(),
+                old_lines: hunk.old_lines(),
+                new_start: hunk.new_start(),
+                new_lines: hunk.new_lines(),
+                lines,
+            });
+        }
+        Ok(hunks)
+    }
+
+    /// 按 `settings.format` 拼接各文件的差异，超出 `max_bytes` 时追加截断说明，
+    /// 并注明被 `exclude_patterns` 省略的文件
+    fn join_patches(
+        files: Vec<FilePatch>,
+        settings: &DiffSettings,
+        scope: &RenderScope,
+    ) -> Result<String> {
+        let (files, truncated) = Self::truncate_patches(files, scope);
+        let excluded = &scope.excluded;
+        if settings.format == DiffFormat::Json {
+            return Ok(Self::json_diff(&files, truncated, excluded).to_string());
+        }
+
+        let mut diff_text: String = files.iter().map(|f| f.text.as_str()).collect();
+        if let Some(marker) = truncated {
+            diff_text.push_str(&marker);
+        }
+        if let Some(note) = Self::excluded_note(excluded) {
+            diff_text.push_str(&note);
+        }
+        Ok(diff_text)
+    }
+
+    /// JSON 格式的差异：`files` 为各文件的 hunk，截断与省略文件的说明放在单独的字段中
+    fn json_diff(
+        files: &[FilePatch],
+        truncated: Option<String>,
+        excluded: &[String],
+    ) -> serde_json::Value {
+        let mut value = serde_json::json!({ "files": files });
+        if let Some(marker) = truncated {
+            value["truncated"] = marker
+                .trim_matches(|c: char| c.is_whitespace() || ".[]".contains(c))
+                .into();
+        }
+        if !excluded.is_empty() {
+            value["excluded"] = excluded.into();
+        }
+        value
+    }
+
+    /// 按 `scope.max_bytes`（0 表示不限制）保留完整的文件 patch，超出时返回截断说明，
+    /// 其中列出因预算用完而未渲染的文件
+    fn truncate_patches(
+        mut files: Vec<FilePatch>,
+        scope: &RenderScope,
+    ) -> (Vec<FilePatch>, Option<String>) {
+        let max_bytes = scope.max_bytes;
+        let total: usize = files.iter().map(|f| f.text.len()).sum();
+        if max_bytes == 0 || (total <= max_bytes && scope.unrendered.is_empty()) {
+            return (files, None);
+        }
+
+        let mut kept_bytes = 0;
+        let mut included = 0;
+        for file in &files {
+            if kept_bytes + file.text.len() > max_bytes {
+                break;
+            }
+            kept_bytes += file.text.len();
+            included += 1;
+        }
+        let omitted_files = files.len() - included;
+
+        // 第一个文件就超过限制时只能在文件内部截断，尽量停在行尾
+        if included == 0 {
+            let text = &mut files[0].text;
+            let mut cut = max_bytes;
+            while !text.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            if let Some(line_end) = text[..cut].rfind('\n') {
+                cut = line_end + 1;
+            }
+            text.truncate(cut);
+            kept_bytes = cut;
+
+            // JSON 格式同样只保留限制以内的行
+            let mut budget = max_bytes;
+            files[0].hunks.retain_mut(|hunk| {
+                hunk.lines.retain(|line| {
+                    let size = line.content.len() + 1;
+                    if size > budget {
+                        budget = 0;
+                        return false;
+                    }
+                    budget -= size;
+                    true
+                });
+                !hunk.lines.is_empty()
+            });
+            included = 1;
+        }
+        files.truncate(included);
+
+        let mut marker = format!(
+            "\n... [diff truncated, {} bytes omitted across {} files",
+            total - kept_bytes,
+            omitted_files
+        );
+        let unrendered = &scope.unrendered;
+        if !unrendered.is_empty() {
+            let listed = unrendered.len().min(MAX_UNRENDERED_LISTED);
+            marker.push_str(&format!(
+                ", plus {} files not rendered: {}",
+                unrendered.len(),
+                unrendered[..listed].join(", ")
+            ));
+            if unrendered.len() > listed {
+                marker.push_str(&format!(" (+{} more)", unrendered.len() - listed));
+            }
+        }
+        marker.push_str("] ...\n");
+        (files, Some(marker))
+    }
+
+    fn delta_path(delta: &DiffDelta) -> String {
+        delta
+            .new_file()
+            .path()
+            .or_else(|| delta.old_file().path())
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn get_staged_diff(&self, settings: &DiffSettings) -> Result<String> {
+        let (files, scope) = self.staged_files(settings)?;
+        Self::join_patches(files, settings, &scope)
+    }
+
+    /// 暂存区差异以及变更文件列表、推测的提交类型与 PMS 单号，只计算一次暂存区差异
+    pub fn review_staged(&self, settings: &DiffSettings) -> Result<StagedReview> {
+        let diff = Self::staged_diff(&self.repo, settings)?;
+        let (patches, scope) = self.staged_files_in(&diff, settings)?;
+        let mut files = Self::changed_files(&diff);
+        let branch = self.branch_name();
+        let suggested_type =
+            (!files.is_empty()).then(|| draft::infer_type(&files, branch.as_deref()).0);
+        let suggested_pms = branch.as_deref().and_then(Self::pms_in);
+        if settings.include_untracked {
+            files.extend(self.untracked_files()?.into_iter().map(|path| ChangedFile {
+                path,
+                status: "untracked",
+                old_path: None,
+            }));
+        }
+        Ok(StagedReview {
+            diff: Self::join_patches(patches, settings, &scope)?,
+            files,
+            suggested_type,
+            suggested_pms,
+        })
+    }
+
+    /// 按文件拆分暂存区差异，超出 `max_bytes` 或省略了文件时同时返回说明
+    pub fn get_staged_diff_by_file(
+        &self,
+        settings: &DiffSettings,
+    ) -> Result<(Vec<FilePatch>, Option<String>)> {
+        let (files, scope) = self.staged_files(settings)?;
+        let (files, truncated) = Self::truncate_patches(files, &scope);
+        let marker = match (truncated, Self::excluded_note(&scope.excluded)) {
+            (Some(truncated), Some(note)) => Some(truncated + &note),
+            (truncated, note) => truncated.or(note),
+        };
+        Ok((files, marker))
+    }
+
+    /// 被 `exclude_patterns` 省略的文件说明
+    fn excluded_note(excluded: &[String]) -> Option<String> {
+        if excluded.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "\n... [{} excluded files changed: {}] ...\n",
+            excluded.len(),
+            excluded.join(", ")
+        ))
+    }
+
+    /// 暂存区中各文件的 patch，以及记录了按 `exclude_patterns` 省略的文件与未渲染文件的范围；
+    /// 未被省略的文件都没有差异时不做省略，避免返回空差异
+    fn staged_files(&self, settings: &DiffSettings) -> Result<(Vec<FilePatch>, RenderScope)> {
+        let diff = Self::staged_diff(&self.repo, settings)?;
+        self.staged_files_in(&diff, settings)
+    }
+
+    fn staged_files_in(
+        &self,
+        diff: &Diff,
+        settings: &DiffSettings,
+    ) -> Result<(Vec<FilePatch>, RenderScope)> {
+        let repo = &self.repo;
+        let untracked = if settings.include_untracked {
+            self.untracked_diff(settings)?
+        } else {
+            None
+        };
+
+        // 包含未跟踪文件时它们同样计入文件数上限
+        let untracked_count = untracked.as_ref().map_or(0, |u| u.deltas().len());
+        let file_count = diff.deltas().len() + untracked_count;
+        if settings.max_files > 0 && file_count > settings.max_files {
+            let counted = if untracked_count > 0 {
+                format!(
+                    "变更了 {} 个文件（含 {} 个未跟踪文件）",
+                    file_count, untracked_count
+                )
+            } else {
+                format!("暂存了 {} 个文件", file_count)
+            };
+            return Err(GitError::TooManyFiles.with(format!(
+                "{}，超过了 {} 个文件的上限，未输出完整差异。请用 get_file_diff 按目录或文件分别查看，或调大 maxFiles。\n\n{}",
+                counted,
+                settings.max_files,
+                self.get_staged_stat()?.summary()
+            )));
+        }
+        // 省略的文件在渲染前确定，不占用字节预算
+        let paths: Vec<String> = diff
+            .deltas()
+            .chain(untracked.iter().flat_map(|u| u.deltas()))
+            .map(|d| Self::delta_path(&d))
+            .collect();
+        let excluded: Vec<String> = paths
+            .iter()
+            .filter(|path| {
+                settings
+                    .exclude_patterns
+                    .iter()
+                    .any(|p| glob_match(p, path))
+            })
+            .cloned()
+            .collect();
+
+        let render = |excluded: Vec<String>| -> Result<(Vec<FilePatch>, RenderScope)> {
+            let mut scope = RenderScope {
+                excluded,
+                ..RenderScope::within(settings.max_bytes)
+            };
+            let mut files = self.render_files(diff, settings, &mut scope)?;
+            if let Some(untracked) = &untracked {
+                files.extend(self.render_files(untracked, settings, &mut scope)?);
+            }
+            Ok((files, scope))
+        };
+        let (mut files, mut scope) = if excluded.len() < paths.len() {
+            render(excluded)?
+        } else {
+            render(Vec::new())?
+        };
+        if files.is_empty() && !scope.excluded.is_empty() {
+            (files, scope) = render(Vec::new())?;
+        }
+
+        if files.is_empty() {
+            if settings.ignore_whitespace
+                && self.has_changes(Self::staged_diff(repo, &DiffSettings::default())?)?
+            {
+                return Err(GitError::NoStagedChanges
+                    .with("暂存区只检测到空白字符变更（已忽略空白差异）。"));
+            }
+            return Err(GitError::NoStagedChanges.with("没有发现已暂存的变更。"));
+        }
+        Ok((files, scope))
+    }
+
+    /// 比较两个版本（分支、标签或提交）的树，即 `git diff from..to`
+    pub fn diff_refs(&self, from: &str, to: &str, settings: &DiffSettings) -> Result<String> {
+        let repo = &self.repo;
+        let from_tree = self.resolve_tree(from)?;
+        let to_tree = self.resolve_tree(to)?;
+
+        let mut opts = Self::diff_options(settings);
+        let mut diff = repo.diff_tree_to_tree(Some(&from_tree), Some(&to_tree), Some(&mut opts))?;
+        diff.find_similar(Some(&mut Self::find_options(settings)))?;
+
+        let mut scope = RenderScope::within(settings.max_bytes);
+        let files = self.render_files(&diff, settings, &mut scope)?;
+        if files.is_empty() {
+            return Err(GitError::NoDiff.with(format!("`{}` 与 `{}` 之间没有差异。", from, to)));
+        }
+
+        Self::join_patches(files, settings, &scope)
+    }
+
+    /// 获取某个提交引入的变更：与第一个父提交比较，根提交与空树比较，即 `git show <rev>`
+    pub fn commit_diff(&self, revision: &str, settings: &DiffSettings) -> Result<String> {
+        let repo = &self.repo;
+        let commit = self.resolve_object(revision)?.peel_to_commit()?;
+        let tree = commit.tree()?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+
+        let mut opts = Self::diff_options(settings);
+        let mut diff =
+            repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut opts))?;
+        diff.find_similar(Some(&mut Self::find_options(settings)))?;
+
+        let mut scope = RenderScope::within(settings.max_bytes);
+        let files = self.render_files(&diff, settings, &mut scope)?;
+        if settings.format == DiffFormat::Json {
+            let (files, truncated) = Self::truncate_patches(files, &scope);
+            let mut value = Self::json_diff(&files, truncated, &[]);
+            value["commit"] = serde_json::json!({
+                "id": commit.id().to_string(),
+                "author": format!(
+                    "{} <{}>",
+                    commit.author().name().unwrap_or_default(),
+                    commit.author().email().unwrap_or_default()
+                ),
+                "message": commit.message().unwrap_or_default().trim_end(),
+                "parents": commit.parent_ids().map(|id| id.to_string()).collect::<Vec<_>>(),
+            });
+            return Ok(value.to_string());
+        }
+
+        let mut text = format!(
+            "commit {}\nAuthor: {} <{}>\n\n",
+            commit.id(),
+            commit.author().name().unwrap_or_default(),
+            commit.author().email().unwrap_or_default()
+        );
+        for line in commit.message().unwrap_or_default().trim_end().lines() {
+            text.push_str(format!("    {}", line).trim_end());
+            text.push('\n');
+        }
+        text.push('\n');
+        if commit.parent_count() > 1 {
+            text.push_str(&format!(
+                "注意：这是一个合并提交（{} 个父提交），以下差异相对于第一个父提交 {}。\n\n",
+                commit.parent_count(),
+                commit.parent_id(0)?
+            ));
+        }
+
+        if files.is_empty() {
+            text.push_str("（该提交没有文件变更）\n");
+            return Ok(text);
+        }
+        text.push_str(&Self::join_patches(files, settings, &scope)?);
+        Ok(text)
+    }
+
+    fn resolve_object(&self, revision: &str) -> Result<git2::Object<'_>> {
+        match self.repo.revparse_single(revision) {
+            Ok(object) => Ok(object),
+            Err(e) if e.code() == ErrorCode::NotFound || e.code() == ErrorCode::Ambiguous => {
+                Err(GitError::RevisionNotFound.with(format!("找不到版本: {}", revision)))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn resolve_tree(&self, revision: &str) -> Result<git2::Tree<'_>> {
+        Ok(self.resolve_object(revision)?.peel_to_tree()?)
+    }
+
+    /// 列出暂存区中相对 HEAD 新增或修改的文件（不含删除）
+    pub fn staged_paths(&self) -> Result<Vec<String>> {
+        let diff = Self::staged_diff(&self.repo, &DiffSettings::default())?;
+        Ok(diff
+            .deltas()
+            .filter(|d| d.status() != Delta::Deleted)
+            .map(|d| Self::delta_path(&d))
+            .collect())
+    }
+
+    /// 读取文件在暂存区中的内容
+    pub fn staged_blob(&self, path: &str) -> Result<Vec<u8>> {
+        let index = self.index()?;
+        let entry = index
+            .get_path(Path::new(path), 0)
+            .ok_or_else(|| GitError::PathNotFound.with(format!("暂存区中没有该文件: {}", path)))?;
+        let blob = self.repo.find_blob(entry.id)?;
+        Ok(blob.content().to_vec())
+    }
+
+    /// 单个路径（文件或目录）的暂存区差异
+    pub fn staged_diff_for_path(&self, path: &str, settings: &DiffSettings) -> Result<String> {
+        let mut opts = Self::diff_options(settings);
+        opts.pathspec(path);
+        let diff = Self::staged_diff_with(&self.repo, opts, settings)?;
+
+        let mut scope = RenderScope::within(settings.max_bytes);
+        let files = self.render_files(&diff, settings, &mut scope)?;
+        if files.is_empty() {
+            return Err(GitError::NoStagedChanges.with(format!("`{}` 没有已暂存的变更。", path)));
+        }
+
+        Self::join_patches(files, settings, &scope)
+    }
+
+    /// 列出暂存区中的变更文件及其变更类型
+    pub fn staged_changes(&self, settings: &DiffSettings) -> Result<Vec<ChangedFile>> {
+        let files = Self::changed_files(&Self::staged_diff(&self.repo, settings)?);
+        if files.is_empty() {
+            return Err(GitError::NoStagedChanges.with("没有发现已暂存的变更。"));
+        }
+        Ok(files)
+    }
+
+    fn changed_files(diff: &Diff) -> Vec<ChangedFile> {
+        diff.deltas()
+            .map(|delta| {
+                let status = match delta.status() {
+                    Delta::Added => "added",
+                    Delta::Deleted => "deleted",
+                    Delta::Renamed => "renamed",
+                    Delta::Copied => "copied",
+                    Delta::Typechange => "typechange",
+                    _ => "modified",
+                };
+                let old_path = match delta.status() {
+                    Delta::Renamed | Delta::Copied => delta
+                        .old_file()
+                        .path()
+                        .map(|p| p.to_string_lossy().to_string()),
+                    _ => None,
+                };
+                ChangedFile {
+                    path: Self::delta_path(&delta),
+                    status,
+                    old_path,
+                }
+            })
+            .collect()
+    }
+
+    /// 根据暂存文件的路径、变更类型与分支名生成提交信息草稿
+    pub fn draft_commit_message(&self, settings: &DiffSettings) -> Result<Draft> {
+        let branch = self.branch_name();
+        Ok(draft::draft(
+            &self.staged_changes(settings)?,
+            branch.as_deref(),
+        ))
+    }
+
+    /// 当前分支名，分离 HEAD 或无法读取时为 `None`
+    fn branch_name(&self) -> Option<String> {
+        self.current_branch()
+            .ok()
+            .filter(|b| !b.detached)
+            .map(|b| b.branch)
+    }
+
+    pub fn get_staged_stat(&self) -> Result<DiffStat> {
+        let repo = &self.repo;
+        let diff = Self::staged_diff(repo, &DiffSettings::default())?;
+
+        let stats = diff.stats()?;
+        if stats.files_changed() == 0 {
+            return Err(GitError::NoStagedChanges.with("没有发现已暂存的变更。"));
+        }
+
+        let mut files = Vec::new();
+        for (idx, delta) in diff.deltas().enumerate() {
+            let path = Self::delta_path(&delta);
+            let old_path = match delta.status() {
+                Delta::Renamed | Delta::Copied => delta
+                    .old_file()
+                    .path()
+                    .map(|p| p.to_string_lossy().to_string()),
+                _ => None,
+            };
+            let (insertions, deletions) = match Patch::from_diff(&diff, idx)? {
+                Some(patch) => {
+                    let (_, insertions, deletions) = patch.line_stats()?;
+                    (insertions, deletions)
+                }
+                None => (0, 0),
+            };
+            files.push(FileStat {
+                path,
+                old_path,
+                insertions,
+                deletions,
+            });
+        }
+
+        Ok(DiffStat {
+            files_changed: stats.files_changed(),
+            insertions: stats.insertions(),
+            deletions: stats.deletions(),
+            files,
+        })
+    }
+
+    pub fn get_unstaged_diff(&self, settings: &DiffSettings) -> Result<String> {
+        let repo = &self.repo;
+
+        // 工作区与索引比较，不依赖 HEAD，新仓库同样适用
+        let mut opts = Self::diff_options(settings);
+        let diff = repo.diff_index_to_workdir(None, Some(&mut opts))?;
+
+        let mut scope = RenderScope::within(settings.max_bytes);
+        let files = self.render_files(&diff, settings, &mut scope)?;
+
+        if files.is_empty() {
+            if settings.ignore_whitespace
+                && self.has_changes(repo.diff_index_to_workdir(None, None)?)?
+            {
+                return Err(GitError::NoUnstagedChanges
+                    .with("工作区只检测到空白字符变更（已忽略空白差异）。"));
+            }
+            return Err(GitError::NoUnstagedChanges.with("没有发现未暂存的变更。"));
+        }
+
+        Self::join_patches(files, settings, &scope)
+    }
+
+    pub fn commit(&self, message: &str, options: &CommitOptions) -> Result<CommitResult> {
+        Self::check_subject(message, options)?;
+        Self::ensure_no_conflicts(&self.index()?)?;
+        if !options.skip_hooks {
+            Self::run_pre_commit_hook(&self.repo)?;
+        }
+
+        // 钩子可能修改了索引，重新读取
+        let mut index = self.index()?;
+        let tree_id = index.write_tree()?;
+        self.commit_tree(message, tree_id, options)
+    }
+
+    /// 只提交暂存区中指定路径的变更，其余已暂存的变更保留在暂存区中
+    pub fn commit_paths(
+        &self,
+        paths: &[String],
+        message: &str,
+        options: &CommitOptions,
+    ) -> Result<CommitResult> {
+        Self::check_subject(message, options)?;
+        Self::ensure_no_conflicts(&self.index()?)?;
+
+        let changes = self.staged_changes(&DiffSettings::default())?;
+        let missing: Vec<&str> = paths
+            .iter()
+            .filter(|p| !changes.iter().any(|c| &c.path == *p))
+            .map(|p| p.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(GitError::NoStagedChanges.with(format!(
+                "以下路径没有已暂存的变更：\n{}",
+                missing.join("\n")
+            )));
+        }
+
+        if !options.skip_hooks {
+            Self::run_pre_commit_hook(&self.repo)?;
+        }
+
+        // 以 HEAD 的树为基础，只替换指定路径的暂存内容，真实的索引保持不变
+        let staged = self.index()?;
+        let head_tree = self.repo.head().and_then(|h| h.peel_to_tree()).ok();
+        let mut updates = Vec::new();
+        for change in changes.iter().filter(|c| paths.contains(&c.path)) {
+            if let Some(old_path) = &change.old_path {
+                if change.status == "renamed" {
+                    updates.push((old_path.clone(), None));
+                }
+            }
+            let entry = staged
+                .get_path(Path::new(&change.path), 0)
+                .map(|e| (e.id, e.mode as i32));
+            updates.push((change.path.clone(), entry));
+        }
+        let tree_id = Self::build_tree(&self.repo, head_tree.as_ref(), updates)?;
+
+        self.commit_tree(message, tree_id, options)
+    }
+
+    /// 以 `base` 为基础，把指定路径替换为给定的对象（`None` 表示删除），逐级用 `TreeBuilder`
+    /// 重建受影响的子树；变空的目录一并删除
+    fn build_tree(
+        repo: &Repository,
+        base: Option<&git2::Tree>,
+        updates: Vec<TreeUpdate>,
+    ) -> Result<Oid> {
+        let mut builder = repo.treebuilder(base)?;
+        let mut nested: BTreeMap<String, Vec<TreeUpdate>> = BTreeMap::new();
+        for (path, entry) in updates {
+            match path.split_once('/') {
+                Some((dir, rest)) => nested
+                    .entry(dir.to_string())
+                    .or_default()
+                    .push((rest.to_string(), entry)),
+                None => match entry {
+                    Some((id, mode)) => {
+                        builder.insert(&path, id, mode)?;
+                    }
+                    None => {
+                        if builder.get(&path)?.is_some() {
+                            builder.remove(&path)?;
+                        }
+                    }
+                },
+            }
+        }
+
+        for (dir, updates) in nested {
+            let subtree = match builder.get(&dir)? {
+                Some(entry) if entry.kind() == Some(git2::ObjectType::Tree) => {
+                    Some(repo.find_tree(entry.id())?)
+                }
+                _ => None,
+            };
+            let tree_id = Self::build_tree(repo, subtree.as_ref(), updates)?;
+            if !repo.find_tree(tree_id)?.is_empty() {
+                builder.insert(&dir, tree_id, FileMode::Tree.into())?;
+            } else if builder.get(&dir)?.is_some() {
+                builder.remove(&dir)?;
+            }
+        }
+        Ok(builder.write()?)
+    }
+
+    /// 严格模式下标题不符合要求时拒绝提交，并列出具体原因
+    fn check_subject(message: &str, options: &CommitOptions) -> Result<()> {
+        if !options.strict {
+            return Ok(());
+        }
+        let subject = message.lines().next().unwrap_or_default();
+        let reasons = message::subject_violations(subject, options.require_type);
+        if reasons.is_empty() {
+            return Ok(());
+        }
+        Err(GitError::InvalidMessage.with(format!(
+            "提交信息未通过检查，请修改后重试（或设置 strict 为 false 跳过检查）：\n{}",
+            reasons.join("\n")
+        )))
+    }
+
+    /// 预览 `commit` 将要创建的提交：执行同样的检查并计算树与最终的提交信息，但不创建提交，
+    /// 不写入任何对象，也不运行 pre-commit 钩子
+    pub fn preview_commit(&self, message: &str, options: &CommitOptions) -> Result<CommitPreview> {
+        Self::check_subject(message, options)?;
+        Self::ensure_no_conflicts(&self.index()?)?;
+
+        let tree_id = self.index_tree_in_memory()?;
+        let prepared = self.prepare_commit(message, tree_id, options, true)?;
+        Ok(CommitPreview {
+            tree: tree_id.to_string(),
+            parents: prepared
+                .parents
+                .iter()
+                .map(|p| p.id().to_string())
+                .collect(),
+            branch: self.current_branch()?.branch,
+            author: format!(
+                "{} <{}>",
+                prepared.sig.name().unwrap_or_default(),
+                prepared.sig.email().unwrap_or_default()
+            ),
+            message: prepared.message,
+        })
+    }
+
+    /// 暂存区对应的树 id。另开一个仓库句柄并在其对象库最前面挂上内存后端，新的树对象只写入内存，
+    /// 句柄释放后即丢弃，仓库中不会留下松散对象
+    fn index_tree_in_memory(&self) -> Result<Oid> {
+        let repo = Repository::open(self.repo.path())?;
+        let odb = repo.odb()?;
+        let _mempack = odb.add_new_mempack_backend(1000)?;
+        let mut index = repo.index()?;
+        Ok(index.write_tree()?)
+    }
+
+    /// 确定提交的签名身份、父提交与追加 trailer 后的最终提交信息，没有变更且不允许空提交时报错
+    fn prepare_commit(
+        &self,
+        message: &str,
+        tree_id: Oid,
+        options: &CommitOptions,
+        head_parent: bool,
+    ) -> Result<PreparedCommit<'_>> {
+        let repo = &self.repo;
+
+        let sig = match (&options.author, &options.date) {
+            (Some((name, email)), None) => Signature::now(name, email)?,
+            (None, None) => repo.signature()?,
+            (author, Some(time)) => {
+                let default_sig;
+                let (name, email) = match author {
+                    Some((name, email)) => (name.as_str(), email.as_str()),
+                    None => {
+                        default_sig = repo.signature()?;
+                        (
+                            default_sig.name().unwrap_or_default(),
+                            default_sig.email().unwrap_or_default(),
+                        )
+                    }
+                };
+                Signature::new(name, email, time)?
+            }
+        };
+
+        let mut message = Self::append_co_authors(message, &options.co_authors)?;
+        if options.signoff {
+            message = Self::append_signoff(&message, &sig);
+        }
+
+        // 尝试获取父提交
+        let parent_commits = match repo.head().and_then(|h| h.peel_to_commit()) {
+            Ok(parent) if head_parent => vec![parent],
+            _ => vec![], // 没有父提交（初始提交）
+        };
+
+        let unchanged = match parent_commits.first() {
+            Some(parent) => parent.tree_id() == tree_id,
+            None => tree_id == Oid::hash_object(git2::ObjectType::Tree, &[])?,
+        };
+        if unchanged && !options.allow_empty {
+            return Err(GitError::NothingToCommit.with(
+                "没有需要提交的变更（暂存区与 HEAD 相同）。如需创建空提交，请设置 allowEmpty。",
+            ));
+        }
+
+        Ok(PreparedCommit {
+            message,
+            sig,
+            parents: parent_commits,
+        })
+    }
+
+    /// 以给定的树创建提交并更新 HEAD
+    fn commit_tree(
+        &self,
+        message: &str,
+        tree_id: Oid,
+        options: &CommitOptions,
+    ) -> Result<CommitResult> {
+        self.commit_tree_onto(message, tree_id, options, true)
+    }
+
+    /// 同 `commit_tree`，`head_parent` 为 false 时创建没有父提交的根提交并把 HEAD 移到该提交
+    fn commit_tree_onto(
+        &self,
+        message: &str,
+        tree_id: Oid,
+        options: &CommitOptions,
+        head_parent: bool,
+    ) -> Result<CommitResult> {
+        let repo = &self.repo;
+        let tree = repo.find_tree(tree_id)?;
+        let PreparedCommit {
+            message,
+            sig,
+            parents: parent_commits,
+        } = self.prepare_commit(message, tree_id, options, head_parent)?;
+        let message = message.as_str();
+
+        let parents_refs: Vec<&git2::Commit> = parent_commits.iter().collect();
+
+        let config = repo.config()?;
+        let sign = options
+            .sign
+            .unwrap_or_else(|| config.get_bool("commit.gpgsign").unwrap_or(false));
+
+        let reflog = if parent_commits.is_empty() {
+            format!("commit (initial): {}", message.lines().next().unwrap_or(""))
+        } else {
+            format!("commit: {}", message.lines().next().unwrap_or(""))
+        };
+        let commit_id = if sign {
+            let key = options
+                .signing_key
+                .clone()
+                .or_else(|| config.get_string("user.signingkey").ok())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "已启用提交签名，但未配置签名密钥（user.signingkey 或 signingKey 选项）。"
+                    )
+                })?;
+            let buffer = repo.commit_create_buffer(&sig, &sig, message, &tree, &parents_refs)?;
+            let content = buffer
+                .as_str()
+                .ok_or_else(|| anyhow!("提交内容不是有效的 UTF-8，无法签名。"))?;
+            let signature = Self::sign_buffer(&config, content, &key)?;
+            let commit_id = repo.commit_signed(content, &signature, None)?;
+            self.update_head(commit_id, &reflog)?;
+            commit_id
+        } else if head_parent {
+            self.retry_locked(|| {
+                repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents_refs)
+            })?
+        } else {
+            // HEAD 已有提交时 libgit2 要求其为第一个父提交，先创建提交再移动 HEAD
+            let commit_id = repo.commit(None, &sig, &sig, message, &tree, &parents_refs)?;
+            self.update_head(commit_id, &reflog)?;
+            commit_id
+        };
+        // 与 git 一样，提交后结束进行中的 revert（删除 REVERT_HEAD 与 MERGE_MSG）
+        if repo.state() == RepositoryState::Revert {
+            repo.cleanup_state()?;
+        }
+
+        let short_id = repo.find_object(commit_id, None)?.short_id()?;
+        let head = self.current_branch()?;
+        Ok(CommitResult {
+            id: commit_id.to_string(),
+            short_id: short_id.as_str().unwrap_or_default().to_string(),
+            branch: head.branch,
+            detached: head.detached,
+            subject: message.lines().next().unwrap_or_default().to_string(),
+        })
+    }
+
+    /// 索引中存在未解决的冲突时拒绝提交，并列出冲突文件
+    fn ensure_no_conflicts(index: &Index) -> Result<()> {
+        if !index.has_conflicts() {
+            return Ok(());
+        }
+
+        Err(GitError::MergeConflict.with(format!(
+            "存在未解决的合并冲突，请先解决以下文件的冲突后再提交：\n{}",
+            Self::conflicted_paths(index)?.join("\n")
+        )))
+    }
+
+    fn conflicted_paths(index: &Index) -> Result<Vec<String>> {
+        let mut paths = Vec::new();
+        for conflict in index.conflicts()? {
+            let conflict = conflict?;
+            let entry = conflict.our.or(conflict.their).or(conflict.ancestor);
+            if let Some(entry) = entry {
+                paths.push(String::from_utf8_lossy(&entry.path).to_string());
+            }
+        }
+        Ok(paths)
+    }
+
+    /// 主仓库的 `.git` 目录；链接工作树中由 `commondir` 文件给出（通常为相对路径）
+    fn common_dir(repo: &Repository) -> PathBuf {
+        let git_dir = repo.path();
+        if !repo.is_worktree() {
+            return git_dir.to_path_buf();
+        }
+        match std::fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common) => git_dir.join(common.trim()),
+            Err(_) => git_dir.to_path_buf(),
+        }
+    }
+
+    /// libgit2 不会执行钩子，这里手动运行 pre-commit，失败时中止提交
+    fn run_pre_commit_hook(repo: &Repository) -> Result<()> {
+        let hooks_dir = match repo.config()?.get_path("core.hooksPath") {
+            Ok(path) => path,
+            // 链接工作树的 `repo.path()` 是 `.git/worktrees/<name>`，钩子位于主仓库的公共目录
+            Err(_) => Self::common_dir(repo).join("hooks"),
+        };
+        let hook = hooks_dir.join("pre-commit");
+        if !Self::is_executable(&hook) {
+            return Ok(());
+        }
+
+        let workdir = repo.workdir().unwrap_or_else(|| repo.path());
+        let output = Command::new(&hook)
+            .current_dir(workdir)
+            .env("GIT_INDEX_FILE", repo.path().join("index"))
+            .output()
+            .map_err(|e| anyhow!("无法执行 pre-commit 钩子 {}: {}", hook.display(), e))?;
+
+        if !output.status.success() {
+            let code = output
+                .status
+                .code()
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "未知".to_string());
+            return Err(GitError::HookFailed.with(format!(
+                "pre-commit 钩子执行失败（退出码 {}），提交已中止：\n{}{}",
+                code,
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn is_executable(path: &Path) -> bool {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    }
+
+    #[cfg(not(unix))]
+    fn is_executable(path: &Path) -> bool {
+        path.is_file()
+    }
+
+    /// 在提交信息末尾追加 `Co-authored-by:` trailer，已存在的合作者不会重复添加
+    fn append_co_authors(message: &str, co_authors: &[String]) -> Result<String> {
+        const TRAILER: &str = "Co-authored-by:";
+
+        let trailer_value = |line: &str| {
+            let line = line.trim();
+            line.get(..TRAILER.len())
+                .filter(|key| key.eq_ignore_ascii_case(TRAILER))
+                .map(|_| line[TRAILER.len()..].trim().to_lowercase())
+        };
+
+        let mut existing: Vec<String> = message.lines().filter_map(trailer_value).collect();
+
+        let mut trailers = Vec::new();
+        for co_author in co_authors {
+            let co_author = co_author.trim();
+            if !Self::is_valid_identity(co_author) {
+                return Err(GitError::InvalidArgument.with(format!(
+                    "合作者格式无效: `{}`，应为 `Name <email>`。",
+                    co_author
+                )));
+            }
+            let key = co_author.to_lowercase();
+            if !existing.contains(&key) {
+                existing.push(key);
+                trailers.push(format!("{} {}", TRAILER, co_author));
+            }
+        }
+
+        if trailers.is_empty() {
+            return Ok(message.to_string());
+        }
+
+        let body = message.trim_end();
+        // 最后一段已经有 Co-authored-by 时直接接在后面，否则空一行另起 trailer 段
+        let last_paragraph = body.rsplit("\n\n").next().unwrap_or("");
+        let separator = if last_paragraph
+            .lines()
+            .any(|line| trailer_value(line).is_some())
+        {
+            "\n"
+        } else {
+            "\n\n"
+        };
+
+        Ok(format!("{}{}{}\n", body, separator, trailers.join("\n")))
+    }
+
+    /// 以签名身份在提交信息末尾追加 `Signed-off-by:` trailer，已存在相同的 trailer 时不重复添加
+    fn append_signoff(message: &str, sig: &Signature) -> String {
+        let trailer = format!(
+            "Signed-off-by: {} <{}>",
+            sig.name().unwrap_or_default(),
+            sig.email().unwrap_or_default()
+        );
+        if message
+            .lines()
+            .any(|line| line.trim().eq_ignore_ascii_case(&trailer))
+        {
+            return message.to_string();
+        }
+
+        let body = message.trim_end();
+        // 与 git 一致：最后一段全部是 trailer 时直接接在后面，否则空一行另起 trailer 段
+        let last_paragraph = body.rsplit("\n\n").next().unwrap_or("");
+        let separator = if body.contains('\n') && last_paragraph.lines().all(message::is_trailer) {
+            "\n"
+        } else {
+            "\n\n"
+        };
+
+        format!("{}{}{}\n", body, separator, trailer)
+    }
+
+    /// 校验 `Name <email>` 形式的身份字符串
+    fn is_valid_identity(identity: &str) -> bool {
+        let Some((name, rest)) = identity.split_once('<') else {
+            return false;
+        };
+        let Some(email) = rest.strip_suffix('>') else {
+            return false;
+        };
+        let name = name.trim();
+        !name.is_empty()
+            && !name.contains('>')
+            && !email.is_empty()
+            && !email.contains(['<', '>', ' '])
+            && email.contains('@')
+    }
+
+    /// 按 `gpg.format` 调用外部程序对提交内容签名，返回 ASCII 格式的签名
+    fn sign_buffer(config: &Config, content: &str, key: &str) -> Result<String> {
+        let format = config
+            .get_string("gpg.format")
+            .unwrap_or_else(|_| "openpgp".to_string());
+
+        let output = match format.as_str() {
+            "openpgp" | "x509" => {
+                let (program_key, default_program) = if format == "x509" {
+                    ("gpg.x509.program", "gpgsm")
+                } else {
+                    ("gpg.program", "gpg")
+                };
+                let program = config
+                    .get_string(program_key)
+                    .unwrap_or_else(|_| default_program.to_string());
+                Self::run_with_stdin(
+                    Command::new(&program).args(["--status-fd=2", "-bsau", key]),
+                    content.as_bytes(),
+                )
+                .map_err(|e| anyhow!("调用 {} 签名失败: {}", program, e))?
+            }
+            "ssh" => {
+                let program = config
+                    .get_string("gpg.ssh.program")
+                    .unwrap_or_else(|_| "ssh-keygen".to_string());
+                // 与 git 一致，`key::` 前缀表示直接给出的公钥，需要写入临时文件并借助 ssh-agent 签名
+                let (key_file, literal) = match key.strip_prefix("key::") {
+                    Some(literal_key) => {
+                        let path = std::env::temp_dir()
+                            .join(format!("git-summarizer-signing-{}.pub", std::process::id()));
+                        std::fs::write(&path, literal_key)?;
+                        (path, true)
+                    }
+                    None => (PathBuf::from(key), false),
+                };
+                let mut command = Command::new(&program);
+                command
+                    .args(["-Y", "sign", "-n", "git", "-f"])
+                    .arg(&key_file);
+                if literal {
+                    command.arg("-U");
+                }
+                let result = Self::run_with_stdin(&mut command, content.as_bytes());
+                if literal {
+                    let _ = std::fs::remove_file(&key_file);
+                }
+                result.map_err(|e| anyhow!("调用 {} 签名失败: {}", program, e))?
+            }
+            other => return Err(anyhow!("不支持的签名格式 gpg.format = {}", other)),
+        };
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "签名失败: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// 以 `gpg --verify` 验证签名，从状态输出中读取签名者与结果
+    fn verify_gpg(
+        config: &Config,
+        format: &str,
+        sig_file: &Path,
+        data: &[u8],
+    ) -> Result<(Option<String>, bool, Option<String>)> {
+        let (program_key, default_program) = if format == "x509" {
+            ("gpg.x509.program", "gpgsm")
+        } else {
+            ("gpg.program", "gpg")
+        };
+        let program = config
+            .get_string(program_key)
+            .unwrap_or_else(|_| default_program.to_string());
+        let mut command = Command::new(&program);
+        command
+            .args(["--status-fd=1", "--verify"])
+            .arg(sig_file)
+            .arg("-");
+        let output = Self::run_with_stdin(&mut command, data)
+            .map_err(|e| anyhow!("调用 {} 验证签名失败: {}", program, e))?;
+
+        let status = String::from_utf8_lossy(&output.stdout);
+        let mut signer = None;
+        let mut good = false;
+        for line in status.lines() {
+            let Some(line) = line.strip_prefix("[GNUPG:] ") else {
+                continue;
+            };
+            let mut fields = line.splitn(3, ' ');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some("GOODSIG"), Some(_), Some(uid)) => {
+                    good = true;
+                    signer = Some(uid.to_string());
+                }
+                (Some("BADSIG" | "EXPSIG" | "EXPKEYSIG" | "REVKEYSIG"), Some(_), Some(uid)) => {
+                    signer = Some(uid.to_string());
+                }
+                // 找不到公钥时只能给出密钥 ID
+                (Some("ERRSIG"), Some(key_id), _) if signer.is_none() => {
+                    signer = Some(key_id.to_string());
+                }
+                _ => {}
+            }
+        }
+        let detail = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Ok((
+            signer,
+            good && output.status.success(),
+            Some(detail).filter(|d| !d.is_empty()),
+        ))
+    }
+
+    /// 以 `ssh-keygen -Y verify` 按 `gpg.ssh.allowedSignersFile` 验证签名，签名者为其中匹配的主体
+    fn verify_ssh(
+        config: &Config,
+        sig_file: &Path,
+        data: &[u8],
+    ) -> Result<(Option<String>, bool, Option<String>)> {
+        let Ok(allowed_signers) = config.get_path("gpg.ssh.allowedSignersFile") else {
+            return Ok((
+                None,
+                false,
+                Some("未配置 gpg.ssh.allowedSignersFile，无法验证 SSH 签名。".to_string()),
+            ));
+        };
+        let program = config
+            .get_string("gpg.ssh.program")
+            .unwrap_or_else(|_| "ssh-keygen".to_string());
+
+        let output = Command::new(&program)
+            .args(["-Y", "find-principals", "-f"])
+            .arg(&allowed_signers)
+            .arg("-s")
+            .arg(sig_file)
+            .output()
+            .map_err(|e| anyhow!("调用 {} 验证签名失败: {}", program, e))?;
+        let principals = String::from_utf8_lossy(&output.stdout).to_string();
+        let Some(principal) = principals
+            .lines()
+            .next()
+            .filter(|_| output.status.success())
+        else {
+            return Ok((
+                None,
+                false,
+                Some(format!("签名的公钥不在 {} 中。", allowed_signers.display())),
+            ));
+        };
+
+        let mut command = Command::new(&program);
+        command
+            .args(["-Y", "verify", "-n", "git", "-f"])
+            .arg(&allowed_signers)
+            .args(["-I", principal, "-s"])
+            .arg(sig_file);
+        let output = Self::run_with_stdin(&mut command, data)
+            .map_err(|e| anyhow!("调用 {} 验证签名失败: {}", program, e))?;
+        let detail = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Ok((
+            Some(principal.to_string()),
+            output.status.success(),
+            Some(detail.trim().to_string()).filter(|d| !d.is_empty()),
+        ))
+    }
+
+    fn run_with_stdin(command: &mut Command, input: &[u8]) -> std::io::Result<Output> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input)?;
+        }
+        child.wait_with_output()
+    }
+
+    /// 将 HEAD（或其指向的分支）移动到新提交，用于 `commit_signed` 之后
+    fn update_head(&self, commit_id: Oid, reflog: &str) -> Result<()> {
+        let repo = &self.repo;
+        match repo.head() {
+            Ok(mut head) => {
+                self.retry_locked(|| head.set_target(commit_id, reflog))?;
+            }
+            Err(_) => {
+                // 新仓库中 HEAD 指向尚未创建的分支
+                let head = repo.find_reference("HEAD")?;
+                let branch = head
+                    .symbolic_target()
+                    .ok_or_else(|| anyhow!("无法解析 HEAD 指向的分支。"))?;
+                self.retry_locked(|| repo.reference(branch, commit_id, false, reflog))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn amend(&self, message: &str) -> Result<String> {
+        let repo = &self.repo;
+        let head_commit = repo.head().and_then(|h| h.peel_to_commit()).map_err(|_| {
+            GitError::NoCommits.with("当前仓库还没有任何提交，无法修改上一次提交。")
+        })?;
+
+        // 有已暂存的变更时，将其一并并入上一次提交
+        let has_staged = Self::staged_diff(repo, &DiffSettings::default())?
+            .deltas()
+            .len()
+            > 0;
+        let tree = if has_staged {
+            let mut index = self.index()?;
+            Self::ensure_no_conflicts(&index)?;
+            let tree_id = index.write_tree()?;
+            repo.find_tree(tree_id)?
+        } else {
+            head_commit.tree()?
+        };
+
+        let commit_id = self.retry_locked(|| {
+            head_commit.amend(Some("HEAD"), None, None, None, Some(message), Some(&tree))
+        })?;
+
+        Ok(format!("Amend successful: {}", commit_id))
+    }
+
+    /// 只修改上一次提交的作者身份与时间，树与提交信息保持不变；作者与提交者使用同一签名
+    pub fn amend_metadata(
+        &self,
+        author: Option<(&str, &str)>,
+        date: Option<Time>,
+    ) -> Result<String> {
+        let repo = &self.repo;
+        let head_commit = repo.head().and_then(|h| h.peel_to_commit()).map_err(|_| {
+            GitError::NoCommits.with("当前仓库还没有任何提交，无法修改上一次提交。")
+        })?;
+
+        let old_author = head_commit.author();
+        let (name, email) = author.unwrap_or((
+            old_author.name().unwrap_or_default(),
+            old_author.email().unwrap_or_default(),
+        ));
+        let sig = Signature::new(name, email, &date.unwrap_or_else(|| old_author.when()))?;
+
+        let commit_id = self.retry_locked(|| {
+            head_commit.amend(Some("HEAD"), Some(&sig), Some(&sig), None, None, None)
+        })?;
+
+        Ok(format!(
+            "Amend successful: {}（作者 {} <{}>，提交内容与提交信息未改变）",
+            commit_id, name, email
+        ))
+    }
+
+    /// 撤销上一次提交（soft reset 到父提交），其变更保留在暂存区与工作区中
+    pub fn undo_last_commit(&self) -> Result<String> {
+        let repo = &self.repo;
+        let head_commit = repo
+            .head()
+            .and_then(|h| h.peel_to_commit())
+            .map_err(|_| GitError::NoCommits.with("当前仓库还没有任何提交，无法撤销。"))?;
+        let parent = head_commit.parent(0).map_err(|_| {
+            GitError::RootCommit.with("上一次提交是仓库的第一个提交（没有父提交），无法撤销。")
+        })?;
+
+        self.retry_locked(|| repo.reset(parent.as_object(), ResetType::Soft, None))?;
+
+        let short_id = head_commit.as_object().short_id()?;
+        Ok(format!(
+            "已撤销提交 {} ({})，其变更仍保留在暂存区中。",
+            short_id.as_str().unwrap_or_default(),
+            head_commit.summary().unwrap_or_default()
+        ))
+    }
+
+    /// 把最近的 `count` 个提交压缩为一个：soft reset 到 `HEAD~count` 后以暂存区的树重新提交，
+    /// 返回新提交与被压缩提交的标题（从新到旧）。包含根提交时新提交成为没有父提交的根提交。
+    /// 提交失败时 HEAD 恢复原位
+    pub fn squash_commits(
+        &self,
+        count: usize,
+        message: &str,
+        options: &CommitOptions,
+    ) -> Result<(CommitResult, Vec<String>)> {
+        let repo = &self.repo;
+        if count < 2 {
+            return Err(GitError::InvalidArgument.with("count 至少为 2。"));
+        }
+        let head_commit = repo
+            .head()
+            .and_then(|h| h.peel_to_commit())
+            .map_err(|_| GitError::NoCommits.with("当前仓库还没有任何提交，无法压缩。"))?;
+        Self::check_subject(message, options)?;
+
+        let mut subjects = Vec::new();
+        // 压缩后的新提交的父提交，压缩到根提交时为 `None`
+        let mut base = Some(head_commit.clone());
+        for _ in 0..count {
+            let Some(commit) = base else {
+                return Err(GitError::InvalidArgument.with(format!(
+                    "count 超出了提交历史的长度：压缩 {} 个提交需要 HEAD~{} 存在。",
+                    count,
+                    count - 1
+                )));
+            };
+            if commit.parent_count() > 1 {
+                return Err(GitError::InvalidArgument.with(format!(
+                    "提交 {} 是合并提交，无法压缩。",
+                    commit.as_object().short_id()?.as_str().unwrap_or_default()
+                )));
+            }
+            subjects.push(commit.summary().unwrap_or_default().to_string());
+            base = commit.parent(0).ok();
+        }
+
+        let Some(base) = base else {
+            // 没有可以 reset 到的提交，直接以暂存区的树创建根提交并移动 HEAD
+            let mut index = self.index()?;
+            Self::ensure_no_conflicts(&index)?;
+            if !options.skip_hooks {
+                Self::run_pre_commit_hook(repo)?;
+            }
+            let tree_id = index.write_tree()?;
+            let result = self.commit_tree_onto(message, tree_id, options, false)?;
+            return Ok((result, subjects));
+        };
+        self.retry_locked(|| repo.reset(base.as_object(), ResetType::Soft, None))?;
+        match self.commit(message, options) {
+            Ok(result) => Ok((result, subjects)),
+            Err(e) => {
+                self.retry_locked(|| repo.reset(head_commit.as_object(), ResetType::Soft, None))?;
+                Err(e)
+            }
+        }
+    }
+
+    /// 撤销某个提交引入的变更，即 `git revert`：在暂存区与工作区中应用反向变更，`commit` 为
+    /// true 时随即创建撤销提交，否则把变更留在暂存区中。发生冲突时保留冲突状态并列出冲突文件
+    pub fn revert_commit(
+        &self,
+        revision: &str,
+        message: Option<&str>,
+        commit: bool,
+        options: &CommitOptions,
+    ) -> Result<RevertResult> {
+        let repo = &self.repo;
+        let target = self.resolve_object(revision)?.peel_to_commit()?;
+        let short_id = target.as_object().short_id()?;
+        let short_id = short_id.as_str().unwrap_or_default();
+        if target.parent_count() > 1 {
+            return Err(
+                GitError::InvalidArgument.with(format!("提交 {} 是合并提交，无法撤销。", short_id))
+            );
+        }
+        if repo.state() != RepositoryState::Clean {
+            return Err(GitError::MergeConflict.with(format!(
+                "仓库正处于未完成的操作中（{:?}），请先完成或中止后再撤销提交。",
+                repo.state()
+            )));
+        }
+        if !self.status_summary()?.staged.is_empty() {
+            return Err(GitError::InvalidArgument
+                .with("暂存区中已有变更，请先提交或取消暂存后再撤销提交。"));
+        }
+
+        // 自定义的提交信息按严格模式检查，git 的标准格式总是允许
+        let message = match message {
+            Some(message) => {
+                Self::check_subject(message, options)?;
+                message.to_string()
+            }
+            None => format!(
+                "Revert \"{}\"\n\nThis reverts commit {}.",
+                target.summary().unwrap_or_default(),
+                target.id()
+            ),
+        };
+
+        match self.retry_locked(|| repo.revert(&target, None)) {
+            Ok(()) => {}
+            Err(e) => match e.downcast_ref::<git2::Error>() {
+                Some(git_error) if git_error.code() == ErrorCode::Conflict => {
+                    return Err(GitError::MergeConflict.with(format!(
+                        "工作区中未提交的修改与撤销的变更冲突，请先提交或暂存（stash）这些修改：{}",
+                        git_error.message()
+                    )));
+                }
+                _ => return Err(e),
+            },
+        }
 
-impl GitHandler {
-    pub fn get_staged_diff() -> Result<String> {
-        let repo = Repository::open(".")?;
+        let index = self.index()?;
+        if index.has_conflicts() {
+            return Err(GitError::MergeConflict.with(format!(
+                "撤销提交 {} 时发生冲突，工作区保留在冲突状态。请解决以下文件的冲突并暂存后再提交，或运行 git revert --abort 放弃撤销：\n{}",
+                short_id,
+                Self::conflicted_paths(&index)?.join("\n")
+            )));
+        }
+        let files = self.status_summary()?.staged;
+        if files.is_empty() {
+            repo.cleanup_state()?;
+            return Err(GitError::NothingToCommit.with(format!(
+                "撤销提交 {} 不会产生任何变更，它引入的变更可能已被撤销。",
+                short_id
+            )));
+        }
 
-        // 尝试获取 HEAD 树，如果不存在（如新仓库），则使用空树
-        let head_tree = match repo.head().and_then(|h| h.peel_to_tree()) {
-            Ok(tree) => Some(tree),
-            Err(_) => None,
+        let commit = if commit {
+            if !options.skip_hooks {
+                Self::run_pre_commit_hook(repo)?;
+            }
+            let tree_id = self.index()?.write_tree()?;
+            Some(self.commit_tree(&message, tree_id, options)?)
+        } else {
+            None
         };
 
-        let mut opts = DiffOptions::new();
-        let diff = repo.diff_tree_to_index(head_tree.as_ref(), None, Some(&mut opts))?;
+        Ok(RevertResult {
+            reverted: target.id().to_string(),
+            message,
+            files,
+            commit,
+        })
+    }
 
-        let mut diff_text = Vec::new();
-        diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
-            diff_text.extend_from_slice(line.content());
-            true
-        })?;
+    pub fn current_branch(&self) -> Result<BranchInfo> {
+        let repo = &self.repo;
+
+        let head = match repo.head() {
+            Ok(head) => head,
+            Err(e) if e.code() == ErrorCode::UnbornBranch => {
+                // 还没有提交时，HEAD 指向一个尚未创建的分支
+                let head = repo.find_reference("HEAD")?;
+                let target = head.symbolic_target().unwrap_or_default();
+                return Ok(BranchInfo {
+                    branch: target.trim_start_matches("refs/heads/").to_string(),
+                    detached: false,
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
 
-        if diff_text.is_empty() {
-            return Err(anyhow!("没有发现已暂存的变更。"));
+        if repo.head_detached()? {
+            let commit = head.peel_to_commit()?;
+            let short_id = commit.as_object().short_id()?;
+            return Ok(BranchInfo {
+                branch: short_id.as_str().unwrap_or_default().to_string(),
+                detached: true,
+            });
         }
 
-        Ok(String::from_utf8_lossy(&diff_text).to_string())
+        Ok(BranchInfo {
+            branch: head.shorthand().unwrap_or_default().to_string(),
+            detached: false,
+        })
     }
 
-    pub fn commit(message: &str) -> Result<String> {
-        let repo = Repository::open(".")?;
-        let mut index = repo.index()?;
-        let tree_id = index.write_tree()?;
-        let tree = repo.find_tree(tree_id)?;
+    /// 列出本地分支，`include_remote` 时一并列出远程跟踪分支；还没有任何分支时返回空列表
+    pub fn branches(&self, include_remote: bool) -> Result<Vec<BranchEntry>> {
+        let filter = if include_remote {
+            None
+        } else {
+            Some(BranchType::Local)
+        };
 
-        let sig = repo.signature()?;
+        let mut entries = Vec::new();
+        for item in self.repo.branches(filter)? {
+            let (branch, kind) = item?;
+            let Some(name) = branch.name()?.map(|n| n.to_string()) else {
+                continue;
+            };
+            // 远程的 HEAD 只是指向默认分支的符号引用
+            if kind == BranchType::Remote && name.ends_with("/HEAD") {
+                continue;
+            }
+            let upstream = match kind {
+                BranchType::Local => branch
+                    .upstream()
+                    .ok()
+                    .and_then(|u| u.name().ok().flatten().map(|n| n.to_string())),
+                BranchType::Remote => None,
+            };
+            entries.push(BranchEntry {
+                name,
+                remote: kind == BranchType::Remote,
+                current: branch.is_head(),
+                upstream,
+            });
+        }
+        Ok(entries)
+    }
 
-        // 尝试获取父提交
-        let parent_commits = match repo.head().and_then(|h| h.peel_to_commit()) {
-            Ok(parent) => vec![parent],
-            Err(_) => vec![], // 没有父提交（初始提交）
+    /// 从分支名中提取 BUG/TASK 单号并统一为大写，如 `feature/task-1234-foo` 得到 `TASK-1234`
+    fn pms_in(branch: &str) -> Option<String> {
+        PMS_PATTERN.find(branch).map(|m| m.as_str().to_uppercase())
+    }
+
+    /// 推送分支。未指定 `remote` 时使用分支配置的上游（`branch.<name>.remote` 与 `.merge`），
+    /// 没有上游时回退到 `origin`
+    pub fn push(&self, remote: Option<&str>, branch: Option<&str>) -> Result<PushResult> {
+        let repo = &self.repo;
+        let branch = match branch {
+            Some(branch) => branch.to_string(),
+            None => {
+                let info = self.current_branch()?;
+                if info.detached {
+                    return Err(GitError::InvalidArgument
+                        .with("当前处于分离 HEAD 状态，请指定要推送的分支。"));
+                }
+                info.branch
+            }
         };
 
-        let parents_refs: Vec<&git2::Commit> = parent_commits.iter().collect();
+        let config = repo.config()?;
+        let upstream_remote = config.get_string(&format!("branch.{}.remote", branch)).ok();
+        let upstream_merge = config.get_string(&format!("branch.{}.merge", branch)).ok();
+        let (remote, source) = match (remote, &upstream_remote) {
+            (Some(remote), _) => (remote.to_string(), "参数指定"),
+            (None, Some(upstream)) => (upstream.clone(), "上游配置"),
+            (None, None) if repo.find_remote("origin").is_ok() => {
+                ("origin".to_string(), "默认远程 origin")
+            }
+            (None, None) => {
+                return Err(GitError::InvalidArgument.with(format!(
+                    "分支 {} 没有设置上游（branch.{}.remote），且仓库中没有 origin，请指定 remote。",
+                    branch, branch
+                )));
+            }
+        };
+        // 推送到上游时沿用其跟踪的远程分支名，否则推送到同名分支
+        let destination = match upstream_merge {
+            Some(merge) if upstream_remote.as_deref() == Some(remote.as_str()) => merge,
+            _ => format!("refs/heads/{}", branch),
+        };
+        let refspec = format!("refs/heads/{}:{}", branch, destination);
+        let remote = remote.as_str();
+
+        let mut remote_handle = match repo.find_remote(remote) {
+            Ok(r) => r,
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                return Err(GitError::InvalidArgument.with(format!("远程仓库不存在: {}", remote)));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut attempts = 0;
+        let mut rejected = None;
+        let result = {
+            let mut callbacks = RemoteCallbacks::new();
+            callbacks.credentials(|url, username, allowed| {
+                // 凭据被拒绝后 libgit2 会反复回调，限制尝试次数以免死循环
+                attempts += 1;
+                if attempts > 3 {
+                    return Err(git2::Error::from_str("没有可用的凭据"));
+                }
+                if allowed.contains(CredentialType::SSH_KEY) {
+                    if let Some(username) = username {
+                        return Cred::ssh_key_from_agent(username);
+                    }
+                }
+                if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                    return Cred::credential_helper(&config, url, username);
+                }
+                Cred::default()
+            });
+            callbacks.push_update_reference(|refname, status| {
+                if let Some(msg) = status {
+                    rejected = Some(format!("{}: {}", refname, msg));
+                }
+                Ok(())
+            });
+
+            let mut push_opts = PushOptions::new();
+            push_opts.remote_callbacks(callbacks);
+            remote_handle.push(&[refspec.as_str()], Some(&mut push_opts))
+        };
+        if let Err(e) = result {
+            return Err(match e.code() {
+                ErrorCode::Auth => GitError::AuthFailed.with(format!(
+                    "推送到 `{}` 时认证失败: {}",
+                    remote,
+                    e.message()
+                )),
+                _ => anyhow!("推送到 `{}` 失败: {}", remote, e.message()),
+            });
+        }
+        if let Some(reason) = rejected {
+            return Err(GitError::PushRejected.with(format!("远程仓库拒绝了推送 ({})", reason)));
+        }
+
+        Ok(PushResult {
+            remote: remote.to_string(),
+            remote_branch: destination
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&destination)
+                .to_string(),
+            branch,
+            source,
+        })
+    }
+
+    /// 在 HEAD 上创建标签：附注标签使用仓库配置的签名，`force` 为真时覆盖同名标签
+    pub fn create_tag(
+        &self,
+        name: &str,
+        message: Option<&str>,
+        annotated: bool,
+        force: bool,
+    ) -> Result<String> {
+        let repo = &self.repo;
+        let target = match repo.head().and_then(|h| h.peel(git2::ObjectType::Commit)) {
+            Ok(target) => target,
+            Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+                return Err(GitError::NoCommits.with("当前仓库还没有提交，无法创建标签。"));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let result = if annotated {
+            let message = message
+                .filter(|m| !m.trim().is_empty())
+                .ok_or_else(|| GitError::InvalidArgument.with("附注标签需要提供 message。"))?;
+            let sig = repo.signature()?;
+            repo.tag(name, &target, &sig, message, force)
+        } else {
+            repo.tag_lightweight(name, &target, force)
+        };
+
+        match result {
+            Ok(_) => {
+                let short_id = target.short_id()?;
+                Ok(format!(
+                    "已在 {} 上创建标签 {}。",
+                    short_id.as_str().unwrap_or_default(),
+                    name
+                ))
+            }
+            Err(e) if e.code() == ErrorCode::Exists => Err(GitError::AlreadyExists
+                .with(format!("标签 `{}` 已存在，如需覆盖请设置 force。", name))),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 获取 HEAD 指向的提交，仓库还没有提交时返回错误
+    pub fn last_commit(&self, date_format: DateFormat) -> Result<LastCommit> {
+        let commit = match self.repo.head() {
+            Ok(head) => head.peel_to_commit()?,
+            Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+                return Err(GitError::NoCommits.with("仓库中还没有任何提交。"));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let author = commit.author();
+        Ok(LastCommit {
+            id: commit.id().to_string(),
+            author: author.name().unwrap_or_default().to_string(),
+            email: author.email().unwrap_or_default().to_string(),
+            date: format_commit_time(author.when(), date_format),
+            message: commit.message().unwrap_or_default().to_string(),
+        })
+    }
+
+    /// 检查提交的 GPG/SSH 签名：提取签名后按签名格式调用 gpg、gpgsm 或 ssh-keygen 验证，
+    /// 验证使用本机的 GPG 密钥环或 `gpg.ssh.allowedSignersFile`
+    pub fn verify_signature(&self, revision: &str) -> Result<SignatureCheck> {
+        let commit = self.resolve_object(revision)?.peel_to_commit()?;
+        let (signature, signed_data) = match self.repo.extract_signature(&commit.id(), None) {
+            Ok(extracted) => extracted,
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                return Ok(SignatureCheck {
+                    commit: commit.id().to_string(),
+                    signed: false,
+                    format: None,
+                    signer: None,
+                    valid: false,
+                    detail: None,
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let signature = String::from_utf8_lossy(&signature).to_string();
+
+        let config = self.repo.config()?;
+        let format = if signature.starts_with("-----BEGIN SSH SIGNATURE-----") {
+            "ssh"
+        } else if signature.starts_with("-----BEGIN SIGNED MESSAGE-----") {
+            "x509"
+        } else {
+            "openpgp"
+        };
+        let sig_file =
+            std::env::temp_dir().join(format!("git-summarizer-verify-{}.sig", std::process::id()));
+        std::fs::write(&sig_file, &signature)?;
+        let result = if format == "ssh" {
+            Self::verify_ssh(&config, &sig_file, &signed_data)
+        } else {
+            Self::verify_gpg(&config, format, &sig_file, &signed_data)
+        };
+        let _ = std::fs::remove_file(&sig_file);
+        let (signer, valid, detail) = result?;
+
+        Ok(SignatureCheck {
+            commit: commit.id().to_string(),
+            signed: true,
+            format: Some(format),
+            signer,
+            valid,
+            detail,
+        })
+    }
+
+    /// 获取文件第 `start` 到 `end` 行（含，从 1 开始）最后由哪些提交修改
+    pub fn blame(&self, path: &str, start: usize, end: usize) -> Result<Vec<BlameHunk>> {
+        if start == 0 || end < start {
+            return Err(GitError::InvalidArgument.with(format!(
+                "无效的行范围 {}-{}：行号从 1 开始，且 end 不能小于 start。",
+                start, end
+            )));
+        }
+
+        let repo = &self.repo;
+        let blob = repo
+            .head()
+            .and_then(|h| h.peel_to_tree())
+            .and_then(|tree| tree.get_path(Path::new(path)))
+            .and_then(|entry| repo.find_blob(entry.id()));
+        let Ok(blob) = blob else {
+            return Err(GitError::PathNotFound
+                .with(format!("文件未被 git 跟踪（HEAD 中不存在）: {}", path)));
+        };
+        // libgit2 对超出文件末尾的行号会返回错误的结果，先截断到实际行数
+        let content = blob.content();
+        let line_count = if content.is_empty() {
+            0
+        } else {
+            content.split(|b| *b == b'\n').count() - usize::from(content.ends_with(b"\n"))
+        };
+        if start > line_count {
+            return Err(GitError::InvalidArgument.with(format!(
+                "{} 只有 {} 行，起始行 {} 超出范围。",
+                path, line_count, start
+            )));
+        }
+        let end = end.min(line_count);
+
+        let mut opts = BlameOptions::new();
+        opts.min_line(start).max_line(end);
+        let blame = repo
+            .blame_file(Path::new(path), Some(&mut opts))
+            .map_err(|e| {
+                GitError::InvalidArgument.with(format!(
+                    "无法获取 {} 的 blame 信息: {}",
+                    path,
+                    e.message()
+                ))
+            })?;
+
+        let mut hunks = Vec::new();
+        for hunk in blame.iter() {
+            let commit = repo.find_commit(hunk.final_commit_id())?;
+            let short_id = commit.as_object().short_id()?;
+            hunks.push(BlameHunk {
+                id: short_id.as_str().unwrap_or_default().to_string(),
+                author: hunk
+                    .final_signature()
+                    .name()
+                    .unwrap_or_default()
+                    .to_string(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+                start_line: hunk.final_start_line(),
+                lines: hunk.lines_in_hunk(),
+            });
+        }
+        Ok(hunks)
+    }
+
+    /// 从 HEAD 开始获取最近 `n` 条提交的标题和作者，没有提交时返回空列表
+    pub fn recent_log(&self, n: usize, date_format: DateFormat) -> Result<Vec<LogEntry>> {
+        let repo = &self.repo;
+
+        match repo.head() {
+            Ok(_) => {}
+            Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(e.into()),
+        }
 
-        let commit_id = repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents_refs)?;
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push_head()?;
+
+        let mut entries = Vec::new();
+        for oid in revwalk.take(n) {
+            let commit = repo.find_commit(oid?)?;
+            let short_id = commit.as_object().short_id()?;
+            entries.push(LogEntry {
+                id: short_id.as_str().unwrap_or_default().to_string(),
+                subject: commit.summary().unwrap_or_default().to_string(),
+                author: commit.author().name().unwrap_or_default().to_string(),
+                date: format_commit_time(commit.author().when(), date_format),
+            });
+        }
 
-        Ok(format!("Commit successful: {}", commit_id))
+        Ok(entries)
     }
 
-    pub fn check_files_status() -> Result<(bool, Vec<String>)> {
-        let repo = Repository::open(".")?;
+    pub fn check_files_status(&self) -> Result<(bool, Vec<String>)> {
+        let repo = &self.repo;
         let mut opts = git2::StatusOptions::new();
         opts.include_untracked(true).recurse_untracked_dirs(true);
         let statuses = repo.statuses(Some(&mut opts))?;
@@ -84,15 +2938,838 @@ impl GitHandler {
         Ok((has_staged, unstaged_files))
     }
 
-    pub fn stage_files(paths: Vec<String>) -> Result<String> {
-        let repo = Repository::open(".")?;
-        let mut index = repo.index()?;
+    /// 工作区是否干净：没有已暂存、未暂存、未跟踪（遵循 `.gitignore`）或冲突的文件；
+    /// 不干净时列出前几个有变更的路径
+    pub fn is_clean(&self) -> Result<CleanStatus> {
+        let mut opts = git2::StatusOptions::new();
+        // 只需判断是否有变更，未跟踪目录不展开
+        opts.include_untracked(true).recurse_untracked_dirs(false);
+        let statuses = self.repo.statuses(Some(&mut opts))?;
+
+        let dirty = statuses
+            .iter()
+            .take(MAX_DIRTY_PATHS)
+            .filter_map(|entry| entry.path().map(|p| p.to_string()))
+            .collect();
+        Ok(CleanStatus {
+            clean: statuses.is_empty(),
+            dirty_count: statuses.len(),
+            dirty,
+        })
+    }
+
+    /// 将所有状态条目归类为已暂存、已修改、未跟踪与冲突
+    pub fn status_summary(&self) -> Result<StatusSummary> {
+        let mut opts = git2::StatusOptions::new();
+        opts.include_untracked(true).recurse_untracked_dirs(true);
+        let statuses = self.repo.statuses(Some(&mut opts))?;
+
+        let mut summary = StatusSummary::default();
+        for entry in statuses.iter() {
+            let Some(path) = entry.path().map(|p| p.to_string()) else {
+                continue;
+            };
+            let status = entry.status();
+
+            if status.is_conflicted() {
+                summary.conflicted.push(path);
+                continue;
+            }
+            if status.intersects(
+                git2::Status::INDEX_NEW
+                    | git2::Status::INDEX_MODIFIED
+                    | git2::Status::INDEX_DELETED
+                    | git2::Status::INDEX_RENAMED
+                    | git2::Status::INDEX_TYPECHANGE,
+            ) {
+                summary.staged.push(path.clone());
+            }
+            if status.is_wt_new() {
+                summary.untracked.push(path);
+            } else if status.intersects(
+                git2::Status::WT_MODIFIED
+                    | git2::Status::WT_DELETED
+                    | git2::Status::WT_RENAMED
+                    | git2::Status::WT_TYPECHANGE,
+            ) {
+                summary.modified.push(path);
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// 列出未跟踪的新文件（展开未跟踪目录中的文件），遵循 `.gitignore`
+    pub fn untracked_files(&self) -> Result<Vec<String>> {
+        let mut opts = git2::StatusOptions::new();
+        opts.include_untracked(true).recurse_untracked_dirs(true);
+        let statuses = self.repo.statuses(Some(&mut opts))?;
+
+        Ok(statuses
+            .iter()
+            .filter(|entry| entry.status().contains(git2::Status::WT_NEW))
+            .filter_map(|entry| entry.path().map(|p| p.to_string()))
+            .collect())
+    }
+
+    /// 暂存指定路径，支持通配符和目录；返回暂存的条目数
+    pub fn stage(&self, paths: &[String]) -> Result<usize> {
+        if paths.is_empty() {
+            return Err(GitError::InvalidArgument.with("未指定需要暂存的文件。"));
+        }
+
+        let repo = &self.repo;
+        let workdir = repo
+            .workdir()
+            .ok_or_else(|| anyhow!("裸仓库不支持暂存文件。"))?
+            .to_path_buf();
+        let mut index = self.index()?;
+        let mut staged = 0;
+
+        for path in paths {
+            let is_glob = path.contains(['*', '?', '[']);
+            let full_path = workdir.join(path);
+
+            if is_glob || full_path.is_dir() {
+                let mut matched = 0;
+                index.add_all(
+                    [path.as_str()],
+                    IndexAddOption::DEFAULT,
+                    Some(&mut |_: &Path, _: &[u8]| {
+                        matched += 1;
+                        0
+                    }),
+                )?;
+                if matched == 0 {
+                    return Err(GitError::PathNotFound
+                        .with(format!("没有与 `{}` 匹配且需要暂存的变更。", path)));
+                }
+                staged += matched;
+            } else if full_path.exists() {
+                index.add_path(Path::new(path))?;
+                staged += 1;
+            } else if index.get_path(Path::new(path), 0).is_some() {
+                // 文件已在工作区删除，暂存这次删除
+                index.remove_path(Path::new(path))?;
+                staged += 1;
+            } else {
+                return Err(GitError::PathNotFound.with(format!("路径不存在: {}", path)));
+            }
+        }
+
+        self.retry_locked(|| index.write())?;
+        Ok(staged)
+    }
+
+    /// 将索引重置为 HEAD（`git reset [paths]`），未指定路径时取消全部暂存；返回被取消暂存的路径
+    pub fn reset_staged(&self, paths: &[String]) -> Result<Vec<String>> {
+        let repo = &self.repo;
+        let head = repo
+            .head()
+            .and_then(|h| h.peel(git2::ObjectType::Commit))
+            .ok();
+        let head_tree = head.as_ref().and_then(|h| h.peel_to_tree().ok());
 
+        let mut opts = DiffOptions::new();
         for path in paths {
-            index.add_path(std::path::Path::new(&path))?;
+            opts.pathspec(path);
+        }
+        let diff = repo.diff_tree_to_index(head_tree.as_ref(), None, Some(&mut opts))?;
+        let unstaged: Vec<String> = diff.deltas().map(|d| Self::delta_path(&d)).collect();
+        if unstaged.is_empty() {
+            return Err(GitError::NoStagedChanges.with("没有需要取消暂存的变更。"));
+        }
+
+        match &head {
+            Some(head) => self.retry_locked(|| repo.reset_default(Some(head), &unstaged))?,
+            None => {
+                // 尚无提交时没有可重置到的 HEAD，直接从索引中移除这些条目
+                let mut index = self.index()?;
+                if paths.is_empty() {
+                    index.clear()?;
+                } else {
+                    for path in &unstaged {
+                        index.remove_path(Path::new(path))?;
+                    }
+                }
+                self.retry_locked(|| index.write())?;
+            }
+        }
+
+        Ok(unstaged)
+    }
+
+    /// 贮藏工作区与暂存区的修改（`git stash push`），返回贮藏提交的 SHA
+    pub fn stash_save(&mut self, message: Option<&str>) -> Result<String> {
+        let sig = self.repo.signature()?;
+        match self.repo.stash_save2(&sig, message, None) {
+            Ok(oid) => Ok(oid.to_string()),
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                Err(GitError::NothingToStash.with("没有可以贮藏的本地修改。"))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 恢复最近一次贮藏并将其从贮藏栈中移除（`git stash pop`），返回该贮藏的说明
+    pub fn stash_pop(&mut self) -> Result<String> {
+        let mut latest = None;
+        self.repo.stash_foreach(|_, message, _| {
+            latest = Some(message.to_string());
+            false
+        })?;
+        let Some(message) = latest else {
+            return Err(GitError::EmptyStash.with("贮藏栈为空，没有可以恢复的贮藏。"));
+        };
+
+        self.repo.stash_pop(0, None)?;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempRepo;
+
+    #[test]
+    fn binary_files_are_summarized_instead_of_dumped() {
+        let repo = TempRepo::new();
+        repo.write("logo.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\xff");
+        repo.git(&["add", "logo.png"]);
+
+        let diff = repo
+            .handler()
+            .get_staged_diff(&DiffSettings::default())
+            .unwrap();
+        assert!(
+            diff.contains("Binary file logo.png changed (0 -> 18 bytes)"),
+            "{}",
+            diff
+        );
+        assert!(!diff.contains("PNG"));
+    }
+
+    #[test]
+    fn commit_is_refused_while_conflicts_are_unresolved() {
+        let repo = TempRepo::new();
+        repo.commit("a.txt", "base\n", "feat: base");
+        repo.git(&["checkout", "-q", "-b", "other"]);
+        repo.commit("a.txt", "other\n", "feat: other");
+        repo.git(&["checkout", "-q", "-"]);
+        repo.commit("a.txt", "main\n", "feat: main");
+
+        let raw = repo.repo();
+        let other = raw.revparse_single("other").unwrap().id();
+        let annotated = raw.find_annotated_commit(other).unwrap();
+        raw.merge(&[&annotated], None, None).unwrap();
+
+        let err = repo
+            .handler()
+            .commit("feat: merge other", &CommitOptions::default())
+            .unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::MergeConflict));
+        assert!(err.to_string().contains("a.txt"), "{}", err);
+    }
+
+    #[test]
+    fn staged_rename_is_reported_as_rename() {
+        let repo = TempRepo::new();
+        let content: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        repo.commit("old.txt", &content, "feat: add old");
+        repo.git(&["mv", "old.txt", "new.txt"]);
+
+        let diff = repo
+            .handler()
+            .get_staged_diff(&DiffSettings::default())
+            .unwrap();
+        assert!(diff.contains("rename from old.txt"), "{}", diff);
+        assert!(diff.contains("rename to new.txt"), "{}", diff);
+        assert!(!diff.contains("deleted file"), "{}", diff);
+    }
+
+    #[test]
+    fn oversized_diff_is_truncated_at_file_boundary() {
+        let repo = TempRepo::new();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let content: String = (1..=10).map(|i| format!("{} line {}\n", name, i)).collect();
+            repo.write(name, content);
         }
+        repo.git(&["add", "."]);
+
+        let settings = DiffSettings {
+            max_bytes: 300,
+            ..DiffSettings::default()
+        };
+        let diff = repo.handler().get_staged_diff(&settings).unwrap();
+        assert!(diff.contains("a.txt line 10\n"), "{}", diff);
+        assert!(!diff.contains("b.txt line 1\n"), "{}", diff);
+        assert!(diff.contains("[diff truncated, "), "{}", diff);
+        assert!(
+            diff.contains("plus 1 files not rendered: c.txt"),
+            "{}",
+            diff
+        );
+    }
+
+    #[test]
+    fn single_oversized_file_is_cut_at_line_end() {
+        let repo = TempRepo::new();
+        let content: String = (1..=100).map(|i| format!("line {}\n", i)).collect();
+        repo.write("big.txt", content);
+        repo.git(&["add", "big.txt"]);
+
+        let settings = DiffSettings {
+            max_bytes: 200,
+            ..DiffSettings::default()
+        };
+        let diff = repo.handler().get_staged_diff(&settings).unwrap();
+        let (patch, marker) = diff.split_once("\n... [diff truncated").unwrap();
+        assert!(patch.len() <= 200);
+        assert!(patch.ends_with('\n'));
+        assert!(marker.contains("omitted across 1 files"), "{}", marker);
+    }
+
+    #[test]
+    fn stash_save_and_pop_restore_dirty_worktree() {
+        let repo = TempRepo::new();
+        repo.commit("a.txt", "a\n", "feat: add a");
+        repo.write("a.txt", "dirty\n");
+
+        let mut git = repo.handler();
+        git.stash_save(Some("wip")).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(repo.path().join("a.txt")).unwrap(),
+            "a\n"
+        );
+
+        let message = git.stash_pop().unwrap();
+        assert!(message.contains("wip"), "{}", message);
+        assert_eq!(
+            std::fs::read_to_string(repo.path().join("a.txt")).unwrap(),
+            "dirty\n"
+        );
+    }
+
+    #[test]
+    fn stash_pop_on_empty_stack_is_an_error() {
+        let repo = TempRepo::new();
+        repo.commit("a.txt", "a\n", "feat: add a");
+
+        let mut git = repo.handler();
+        let err = git.stash_pop().unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::EmptyStash));
+        assert!(err.to_string().contains("贮藏栈为空"), "{}", err);
+        let err = git.stash_save(None).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::NothingToStash));
+        assert!(err.to_string().contains("没有可以贮藏"), "{}", err);
+    }
+
+    #[test]
+    fn empty_commit_is_refused_unless_allowed() {
+        let repo = TempRepo::new();
+        let first = repo.commit("a.txt", "a\n", "feat: add a");
+        let git = repo.handler();
+
+        let err = git
+            .commit("chore: trigger ci", &CommitOptions::default())
+            .unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::NothingToCommit));
+
+        let options = CommitOptions {
+            allow_empty: true,
+            ..CommitOptions::default()
+        };
+        git.commit("chore: trigger ci", &options).unwrap();
+        let head = git.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.parent_id(0).unwrap().to_string(), first);
+        assert_eq!(head.tree_id(), head.parent(0).unwrap().tree_id());
+    }
+
+    #[test]
+    fn directory_outside_a_repository_is_not_a_repo() {
+        let dir = crate::test_support::TempDir::new();
+        let err = GitHandler::open(dir.path().to_str().unwrap())
+            .err()
+            .unwrap();
+        assert_eq!(GitError::of(&err), Some(GitError::NotARepo));
+        assert!(err.to_string().contains("不是 git 仓库"), "{}", err);
+    }
+
+    #[test]
+    fn empty_repository_has_no_staged_changes() {
+        let repo = TempRepo::new();
+        let git = repo.handler();
+
+        let err = git.get_staged_diff(&DiffSettings::default()).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::NoStagedChanges));
+        assert!(err.to_string().contains("没有发现已暂存的变更"), "{}", err);
+        let err = git
+            .commit("feat: nothing", &CommitOptions::default())
+            .unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::NothingToCommit));
+    }
+
+    #[test]
+    fn repository_is_discovered_from_subdirectory() {
+        let repo = TempRepo::new();
+        repo.commit("src/main.rs", "fn main() {}\n", "feat: add main");
+
+        let git = GitHandler::open(repo.path().join("src").to_str().unwrap()).unwrap();
+        assert_eq!(
+            git.workdir().unwrap().canonicalize().unwrap(),
+            repo.path().canonicalize().unwrap()
+        );
+    }
+
+    #[test]
+    fn commit_through_linked_worktree_targets_its_head() {
+        let repo = TempRepo::new();
+        let base = repo.commit("a.txt", "a\n", "feat: add a");
+        let worktree = crate::test_support::TempDir::new();
+        let worktree_path = worktree.path().join("wt");
+        repo.git(&[
+            "worktree",
+            "add",
+            "-q",
+            "-b",
+            "feature",
+            worktree_path.to_str().unwrap(),
+        ]);
+        std::fs::write(worktree_path.join("b.txt"), "b\n").unwrap();
+
+        let git = GitHandler::open(worktree_path.to_str().unwrap()).unwrap();
+        git.stage(&["b.txt".to_string()]).unwrap();
+        let result = git
+            .commit("feat: add b", &CommitOptions::default())
+            .unwrap();
+
+        let raw = repo.repo();
+        let feature = raw.revparse_single("feature").unwrap().id();
+        assert_eq!(feature.to_string(), result.id);
+        let main_head = raw.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(main_head.id().to_string(), base);
+    }
+
+    #[test]
+    fn undo_last_commit_keeps_changes_staged() {
+        let repo = TempRepo::new();
+        let first = repo.commit("a.txt", "a\n", "feat: add a");
+        repo.commit("b.txt", "b\n", "feat: add b");
+
+        let git = repo.handler();
+        let message = git.undo_last_commit().unwrap();
+        assert!(message.contains("feat: add b"), "{}", message);
+        let head = git.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id().to_string(), first);
+        assert_eq!(git.staged_paths().unwrap(), vec!["b.txt"]);
+    }
+
+    #[test]
+    fn undo_refuses_root_commit_and_empty_repository() {
+        let repo = TempRepo::new();
+        let git = repo.handler();
+        let err = git.undo_last_commit().unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::NoCommits));
+
+        let root = repo.commit("a.txt", "a\n", "feat: add a");
+        let err = git.undo_last_commit().unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::RootCommit));
+        assert!(err.to_string().contains("第一个提交"), "{}", err);
+        let head = git.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id().to_string(), root);
+    }
+
+    #[test]
+    fn git_dir_and_work_tree_are_read_from_env() {
+        let repo = TempRepo::new();
+        repo.commit("a.txt", "a\n", "feat: add a");
+        let work_tree = crate::test_support::TempDir::new();
+        work_tree.write("a.txt", "a\n");
+        work_tree.write("b.txt", "b\n");
+
+        let _env = crate::test_support::env_lock();
+        std::env::set_var("GIT_DIR", repo.path().join(".git"));
+        std::env::set_var("GIT_WORK_TREE", work_tree.path());
+        let opened = GitHandler::open_from_env();
+        std::env::remove_var("GIT_DIR");
+        std::env::remove_var("GIT_WORK_TREE");
+
+        let git = opened.unwrap();
+        assert_eq!(
+            git.workdir().unwrap().canonicalize().unwrap(),
+            work_tree.path().canonicalize().unwrap()
+        );
+        git.stage(&["b.txt".to_string()]).unwrap();
+        let diff = git.get_staged_diff(&DiffSettings::default()).unwrap();
+        assert!(diff.contains("+b"), "{}", diff);
+    }
+
+    #[test]
+    fn signoff_uses_committer_identity_once() {
+        let repo = TempRepo::new();
+        repo.write("a.txt", "a\n");
+        let git = repo.handler();
+        git.stage(&["a.txt".to_string()]).unwrap();
+
+        let options = CommitOptions {
+            signoff: true,
+            ..CommitOptions::default()
+        };
+        git.commit("feat: add a\n\nLog: 添加 a", &options).unwrap();
+        let head = git.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(
+            head.message().unwrap().trim_end(),
+            "feat: add a\n\nLog: 添加 a\nSigned-off-by: Test <test@example.com>"
+        );
+        assert_eq!(head.committer().email(), Some("test@example.com"));
+
+        let signed = "feat: add b\n\nSigned-off-by: Test <test@example.com>";
+        assert_eq!(
+            GitHandler::append_signoff(signed, &head.committer()),
+            signed
+        );
+    }
+
+    #[test]
+    fn commit_on_detached_head_reports_detached() {
+        let repo = TempRepo::new();
+        let first = repo.commit("a.txt", "a\n", "feat: add a");
+        let second = repo.commit("b.txt", "b\n", "feat: add b");
+        let branch = repo.git(&["branch", "--show-current"]);
+        repo.git(&["checkout", "-q", "--detach", &first]);
+        repo.write("c.txt", "c\n");
+
+        let git = repo.handler();
+        git.stage(&["c.txt".to_string()]).unwrap();
+        let result = git
+            .commit("feat: add c", &CommitOptions::default())
+            .unwrap();
+
+        assert!(result.detached);
+        assert_eq!(result.branch, result.short_id);
+        assert!(git.repo.head_detached().unwrap());
+        let head = git.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id().to_string(), result.id);
+        assert_eq!(head.parent_id(0).unwrap().to_string(), first);
+        let branch = git.repo.revparse_single(branch.trim()).unwrap();
+        assert_eq!(branch.id().to_string(), second);
+    }
+
+    #[test]
+    fn preview_commit_creates_no_commit() {
+        let repo = TempRepo::new();
+        let first = repo.commit("a.txt", "a\n", "feat: add a");
+        repo.write("b.txt", "b\n");
+        let git = repo.handler();
+        git.stage(&["b.txt".to_string()]).unwrap();
+
+        let options = CommitOptions {
+            signoff: true,
+            ..CommitOptions::default()
+        };
+        let objects = |dir: &Path| -> usize {
+            std::fs::read_dir(dir)
+                .unwrap()
+                .flatten()
+                .filter(|entry| entry.file_name().len() == 2)
+                .map(|entry| std::fs::read_dir(entry.path()).unwrap().count())
+                .sum()
+        };
+        let objects_dir = repo.path().join(".git/objects");
+        let loose_before = objects(&objects_dir);
+        let index_before = std::fs::read(repo.path().join(".git/index")).unwrap();
+        let refs_before = repo.git(&["show-ref", "--head"]);
+
+        let preview = git.preview_commit("feat: add b", &options).unwrap();
+        assert_eq!(objects(&objects_dir), loose_before);
+        assert_eq!(
+            std::fs::read(repo.path().join(".git/index")).unwrap(),
+            index_before
+        );
+        assert_eq!(repo.git(&["show-ref", "--head"]), refs_before);
+        assert_eq!(preview.parents, vec![first.clone()]);
+        assert_eq!(preview.author, "Test <test@example.com>");
+        assert!(preview
+            .message
+            .ends_with("Signed-off-by: Test <test@example.com>\n"));
+
+        let head = git.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id().to_string(), first);
+        assert_eq!(git.staged_paths().unwrap(), vec!["b.txt"]);
+
+        let result = git.commit("feat: add b", &options).unwrap();
+        let head = git
+            .repo
+            .find_commit(Oid::from_str(&result.id).unwrap())
+            .unwrap();
+        assert_eq!(head.tree_id().to_string(), preview.tree);
+        assert_eq!(head.message(), Some(preview.message.as_str()));
+    }
+
+    #[test]
+    fn excluded_files_are_listed_instead_of_rendered() {
+        let repo = TempRepo::new();
+        repo.write("Cargo.lock", "# generated\n");
+        repo.write("src/main.rs", "fn main() {}\n");
+        let git = repo.handler();
+        git.stage(&["Cargo.lock".into(), "src/main.rs".into()])
+            .unwrap();
+
+        let diff = git.get_staged_diff(&DiffSettings::default()).unwrap();
+        assert!(diff.contains("+fn main() {}"), "{}", diff);
+        assert!(!diff.contains("# generated"), "{}", diff);
+        assert!(
+            diff.contains("[1 excluded files changed: Cargo.lock]"),
+            "{}",
+            diff
+        );
+
+        let settings = DiffSettings {
+            exclude_patterns: vec!["src/*.rs".to_string()],
+            ..DiffSettings::default()
+        };
+        let diff = git.get_staged_diff(&settings).unwrap();
+        assert!(diff.contains("# generated"), "{}", diff);
+        assert!(!diff.contains("fn main"), "{}", diff);
+    }
+
+    #[test]
+    fn excluded_files_are_shown_when_nothing_else_changed() {
+        let repo = TempRepo::new();
+        repo.write("Cargo.lock", "# generated\n");
+        let git = repo.handler();
+        git.stage(&["Cargo.lock".into()]).unwrap();
+
+        let diff = git.get_staged_diff(&DiffSettings::default()).unwrap();
+        assert!(diff.contains("+# generated"), "{}", diff);
+        assert!(!diff.contains("excluded files changed"), "{}", diff);
+    }
+
+    #[test]
+    fn staged_submodule_update_is_annotated() {
+        let sub = TempRepo::new();
+        let old = sub.commit("lib.rs", "v1\n", "feat: first version");
+        let repo = TempRepo::new();
+        repo.git(&[
+            "-c",
+            "protocol.file.allow=always",
+            "submodule",
+            "add",
+            "-q",
+            sub.path_str(),
+            "vendor/lib",
+        ]);
+        repo.git(&["commit", "-q", "-m", "chore: add submodule"]);
+
+        let new = sub.commit("lib.rs", "v2\n", "feat: second version");
+        repo.git(&["-C", "vendor/lib", "fetch", "-q", "origin"]);
+        repo.git(&["-C", "vendor/lib", "checkout", "-q", &new]);
+        repo.git(&["add", "vendor/lib"]);
+
+        let diff = repo
+            .handler()
+            .get_staged_diff(&DiffSettings::default())
+            .unwrap();
+        assert!(
+            diff.contains(&format!(
+                "Submodule vendor/lib {}..{}:\n  > feat: second version\n",
+                &old[..7],
+                &new[..7]
+            )),
+            "{}",
+            diff
+        );
+    }
+
+    #[test]
+    fn commit_paths_commits_only_listed_nested_paths() {
+        let repo = TempRepo::new();
+        repo.commit("src/lib/a.rs", "a\n", "feat: add a");
+        repo.write("src/lib/a.rs", "a2\n");
+        repo.write("src/lib/b.rs", "b\n");
+        repo.write("docs/c.md", "c\n");
+        let git = repo.handler();
+        git.stage(&["src".to_string(), "docs".to_string()]).unwrap();
+
+        git.commit_paths(
+            &["src/lib/b.rs".to_string()],
+            "feat: add b",
+            &CommitOptions::default(),
+        )
+        .unwrap();
+
+        let tree = git.repo.head().unwrap().peel_to_tree().unwrap();
+        let blob = |path: &str| {
+            let entry = tree.get_path(Path::new(path)).unwrap();
+            let blob = git.repo.find_blob(entry.id()).unwrap();
+            String::from_utf8(blob.content().to_vec()).unwrap()
+        };
+        assert_eq!(blob("src/lib/a.rs"), "a\n");
+        assert_eq!(blob("src/lib/b.rs"), "b\n");
+        assert!(tree.get_path(Path::new("docs/c.md")).is_err());
+        let mut staged = git.staged_paths().unwrap();
+        staged.sort();
+        assert_eq!(staged, vec!["docs/c.md", "src/lib/a.rs"]);
+    }
+
+    #[test]
+    fn squash_including_root_commit_creates_new_root() {
+        let repo = TempRepo::new();
+        repo.commit("a.txt", "a\n", "feat: add a");
+        repo.commit("b.txt", "b\n", "feat: add b");
+
+        let git = repo.handler();
+        let (result, squashed) = git
+            .squash_commits(2, "feat: add a and b", &CommitOptions::default())
+            .unwrap();
+
+        assert_eq!(squashed, vec!["feat: add b", "feat: add a"]);
+        let head = git.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id().to_string(), result.id);
+        assert_eq!(head.parent_count(), 0);
+        let tree = head.tree().unwrap();
+        assert!(tree.get_name("a.txt").is_some() && tree.get_name("b.txt").is_some());
+    }
+
+    #[test]
+    fn squash_beyond_root_commit_is_rejected() {
+        let repo = TempRepo::new();
+        repo.commit("a.txt", "a\n", "feat: add a");
+        repo.commit("b.txt", "b\n", "feat: add b");
+
+        let git = repo.handler();
+        let err = git
+            .squash_commits(3, "feat: everything", &CommitOptions::default())
+            .unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::InvalidArgument));
+    }
+
+    #[test]
+    fn precondition_failures_carry_error_codes() {
+        let repo = TempRepo::new();
+        let first = repo.commit("a.txt", "a\n", "feat: add a");
+        let git = repo.handler();
+
+        let err = git.stage(&[]).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::InvalidArgument));
+        let err = git.stage(&["*.md".to_string()]).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::PathNotFound));
+
+        let err = git
+            .diff_refs("HEAD", &first, &DiffSettings::default())
+            .unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::NoDiff));
+
+        let err = git.create_tag("v1", None, true, false).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::InvalidArgument));
+        git.create_tag("v1", None, false, false).unwrap();
+        let err = git.create_tag("v1", None, false, false).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::AlreadyExists));
+
+        repo.git(&["checkout", "-q", "--detach"]);
+        let err = git.push(None, None).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::InvalidArgument));
+    }
+
+    #[test]
+    fn revert_preconditions_carry_error_codes() {
+        let repo = TempRepo::new();
+        repo.commit("a.txt", "a\n", "feat: add a");
+        let second = repo.commit("a.txt", "b\n", "fix: change a");
+        let git = repo.handler();
+        let options = CommitOptions::default();
+
+        repo.write("b.txt", "b\n");
+        repo.git(&["add", "b.txt"]);
+        let err = git
+            .revert_commit(&second, None, true, &options)
+            .unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::InvalidArgument));
+        repo.git(&["reset", "-q"]);
+
+        std::fs::write(
+            repo.path().join(".git/REVERT_HEAD"),
+            format!("{}\n", second),
+        )
+        .unwrap();
+        let err = git
+            .revert_commit(&second, None, true, &options)
+            .unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::MergeConflict));
+    }
+
+    #[test]
+    fn max_files_counts_untracked_files_when_included() {
+        let repo = TempRepo::new();
+        repo.commit("a.txt", "a\n", "feat: add a");
+        let git = repo.handler();
+
+        repo.write("a.txt", "b\n");
+        repo.git(&["add", "a.txt"]);
+        repo.write("new1.txt", "1\n");
+        repo.write("new2.txt", "2\n");
+
+        let settings = DiffSettings {
+            max_files: 2,
+            ..DiffSettings::default()
+        };
+        assert!(git.get_staged_diff(&settings).is_ok());
+
+        let settings = DiffSettings {
+            include_untracked: true,
+            ..settings
+        };
+        let err = git.get_staged_diff(&settings).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::TooManyFiles));
+        assert!(err.to_string().contains("含 2 个未跟踪文件"), "{}", err);
+    }
+
+    #[test]
+    fn review_staged_reports_files_type_and_pms_from_one_diff() {
+        let repo = TempRepo::new();
+        repo.commit("src/lib.rs", "fn a() {}\n", "feat: add lib");
+        repo.git(&["checkout", "-q", "-b", "fix/bug-42-crash"]);
+        let git = repo.handler();
+
+        repo.write("src/lib.rs", "fn a() { b() }\n");
+        repo.git(&["add", "src/lib.rs"]);
+        repo.write("notes.txt", "todo\n");
+
+        let settings = DiffSettings {
+            include_untracked: true,
+            ..DiffSettings::default()
+        };
+        let review = git.review_staged(&settings).unwrap();
+        assert!(review.diff.contains("+fn a() { b() }"), "{}", review.diff);
+        assert!(review.diff.contains("+todo"), "{}", review.diff);
+        let files: Vec<_> = review
+            .files
+            .iter()
+            .map(|f| (f.path.as_str(), f.status))
+            .collect();
+        assert_eq!(
+            files,
+            [("src/lib.rs", "modified"), ("notes.txt", "untracked")]
+        );
+        assert_eq!(review.suggested_type, Some("fix"));
+        assert_eq!(review.suggested_pms.as_deref(), Some("BUG-42"));
+    }
+
+    #[test]
+    fn blame_rejects_lines_beyond_the_end_of_the_file() {
+        let repo = TempRepo::new();
+        repo.commit("empty.txt", "", "feat: add empty file");
+        repo.commit("two.txt", "one\ntwo\n", "feat: add two lines");
+        let git = repo.handler();
+
+        let err = git.blame("empty.txt", 1, 1).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::InvalidArgument));
+        assert!(err.to_string().contains("只有 0 行"), "{}", err);
 
-        index.write()?;
-        Ok("Files staged successfully".to_string())
+        let err = git.blame("two.txt", 3, 5).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::InvalidArgument));
+        let hunks = git.blame("two.txt", 2, 9).unwrap();
+        assert_eq!(hunks.len(), 1);
     }
 }
@@ -0,0 +1,82 @@
+use crate::server;
+use anyhow::Result;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use futures_util::stream;
+use serde_json::Value;
+use std::convert::Infallible;
+use tracing::{info, trace, warn};
+
+/// 以 MCP Streamable HTTP 方式提供服务：客户端向 `/mcp` POST JSON-RPC 消息，
+/// 响应按 `Accept` 头以 SSE 事件流或普通 JSON 返回
+pub async fn serve(port: u16) -> Result<()> {
+    let app = Router::new().route("/mcp", post(handle_post).get(handle_get));
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await?;
+    info!("HTTP 传输已启动: http://127.0.0.1:{}/mcp", port);
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+async fn handle_post(headers: HeaderMap, body: String) -> Response {
+    if server::echo_messages() {
+        trace!("收到请求: {}", body);
+    }
+    let message: Value = match serde_json::from_str(&body) {
+        Ok(message) => message,
+        Err(e) => {
+            warn!("JSON 解析失败: {}", e);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(server::parse_error_response(&body, &e)),
+            )
+                .into_response();
+        }
+    };
+
+    // git2 的调用是阻塞的，放到阻塞线程池中执行
+    let response =
+        match tokio::task::spawn_blocking(move || server::dispatch_message(message)).await {
+            Ok(response) => response,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+    // 通知只能随 SSE 事件流发送，其余情况下直接丢弃，避免在队列中堆积
+    let notifications = server::take_notifications();
+
+    // 只包含通知时没有响应内容
+    let Some(response) = response else {
+        return StatusCode::ACCEPTED.into_response();
+    };
+    if server::echo_messages() {
+        trace!("发送响应: {}", response);
+    }
+
+    let wants_sse = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+    if !wants_sse {
+        return Json(response).into_response();
+    }
+
+    // 服务端通知与批量请求的每个响应都作为独立的 SSE 事件发送
+    let mut messages = notifications;
+    match response {
+        Value::Array(items) => messages.extend(items),
+        single => messages.push(single),
+    }
+    let events = messages
+        .into_iter()
+        .map(|r| Ok::<_, Infallible>(Event::default().event("message").data(r.to_string())));
+
+    Sse::new(stream::iter(events)).into_response()
+}
+
+/// 暂不支持服务端主动推送的 GET 事件流
+async fn handle_get() -> StatusCode {
+    StatusCode::METHOD_NOT_ALLOWED
+}
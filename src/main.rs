@@ -1,291 +1,510 @@
+mod cancel;
+mod config;
+mod draft;
 mod git;
+mod http;
+mod message;
 mod protocol;
+mod registry;
+mod server;
+#[cfg(test)]
+mod test_support;
+mod word_diff;
 
 use anyhow::Result;
-use git::GitHandler;
-use protocol::{CallToolParams, InitializeParams, JsonRpcRequest, Tool};
+use clap::{Parser, ValueEnum};
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::mpsc;
+use tracing::{debug, trace, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter};
 
-use serde_json::json;
-use std::io::{self, BufRead, Write};
+const DEFAULT_HTTP_PORT: u16 = 3000;
 
-use std::sync::Mutex;
-
-struct ServerConfig {
-    commit_format: Vec<String>,
-    extra_constraints: Vec<String>,
+#[derive(Clone, Copy, ValueEnum)]
+enum Transport {
+    Stdio,
+    Http,
 }
 
-lazy_static::lazy_static! {
-    static ref CONFIG: Mutex<ServerConfig> = Mutex::new(ServerConfig {
-        commit_format: vec![
-            "<type>[optional scope]: <english description>".to_string(),
-            "".to_string(),
-            "[English body]".to_string(),
-            "".to_string(),
-            "[Chinese body]".to_string(),
-            "".to_string(),
-            "Log: [short description of the change use chinese language]".to_string(),
-            "PMS: <BUG-number> or <TASK-number> (必须包含 'BUG-' 或 'TASK-' 前缀。如果没有，必须询问用户；若用户明确不提供，则从提交信息中删除此行)".to_string(),
-            "Issue: Fixes #xxx (所修复的bug对应的github issue，其中 \"Fixes #xxx\" 是github关闭issue的规则，此处内容只需要满足github的要求即可。如果没有，必须询问用户；若用户明确不提供，则从提交信息中删除此行)".to_string(),
-            "Influence: Explain in Chinese the potential impact of this submission.".to_string(),
-        ],
-        extra_constraints: vec![
-            "Body 的每一行不得超过 80 个字符。".to_string(),
-            "中英文 Body 必须成对出现，不得只写其中一个。".to_string(),
-        ],
-    });
-}
+/// 为 AI 助手提供 git 暂存、差异与提交工具的 MCP 服务
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// 传输方式
+    #[arg(long, value_enum, default_value = "stdio")]
+    transport: Transport,
 
+    /// HTTP 传输监听的端口
+    #[arg(long, default_value_t = DEFAULT_HTTP_PORT)]
+    port: u16,
+
+    /// 仓库路径，可以是仓库内的任意子目录；对应 `repoPath` 配置项
+    #[arg(long)]
+    repo: Option<String>,
+
+    /// 日志级别（如 debug、info、warn），优先于 RUST_LOG
+    #[arg(long)]
+    log_level: Option<String>,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let stdin = io::stdin();
-    let mut lines = stdin.lock().lines();
+    let cli = Cli::parse();
 
-    while let Some(Ok(line)) = lines.next() {
-        eprintln!("收到请求: {}", line);
-        let request: JsonRpcRequest = match serde_json::from_str(&line) {
-            Ok(req) => req,
-            Err(e) => {
-                eprintln!("JSON 解析失败: {}", e);
-                continue;
-            }
-        };
+    // stdout 专用于 JSON-RPC 输出，日志一律写到 stderr；默认只输出 info 及以上级别
+    let filter = match &cli.log_level {
+        Some(level) => EnvFilter::try_new(level)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let (filter, reload_handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(io::stderr).with_ansi(false))
+        .init();
+    let _ = server::LOG_FILTER.set(reload_handle);
+    server::init_quiet();
 
-        let is_notification = request.id.is_none();
-
-        let response_payload = match request.method.as_str() {
-            "initialize" => {
-                if let Some(params_val) = &request.params {
-                    if let Ok(params) =
-                        serde_json::from_value::<InitializeParams>(params_val.clone())
-                    {
-                        if let Some(options) = params.options {
-                            let mut config = CONFIG.lock().unwrap();
-                            if let Some(format_val) = options.get("commitFormat") {
-                                if let Some(s) = format_val.as_str() {
-                                    config.commit_format = vec![s.to_string()];
-                                } else if let Some(arr) = format_val.as_array() {
-                                    config.commit_format = arr
-                                        .iter()
-                                        .filter_map(|v| v.as_str())
-                                        .map(|s| s.to_string())
-                                        .collect();
-                                }
-                            }
-
-
-                            if let Some(constraints) =
-                                options.get("extraConstraints").and_then(|v| v.as_array())
-                            {
-                                config.extra_constraints = constraints
-                                    .iter()
-                                    .filter_map(|v| v.as_str())
-                                    .map(|s| s.to_string())
-                                    .collect();
-                            }
-                        }
-                    }
-                }
+    if let Some(repo) = cli.repo {
+        server::set_repo_path(repo);
+    }
+    if let Err(e) = server::open_repository() {
+        debug!("启动时未能打开仓库，将在首次调用工具时重试: {}", e);
+    }
 
-                Some(json!({
-                    "protocolVersion": "2024-11-05",
-                    "capabilities": {
-                        "tools": {
-                            "listChanged": true
-                        }
-                    },
-                    "serverInfo": {
-                        "name": "git-summarizer",
-                        "version": "0.1.0"
-                    }
-                }))
+    match cli.transport {
+        Transport::Stdio => run_stdio(),
+        Transport::Http => http::serve(cli.port).await,
+    }
+}
+
+fn run_stdio() -> Result<()> {
+    run(BufReader::new(io::stdin()), io::stdout().lock())
+}
+
+/// 输入流的编码，由 BOM 或首个含 NUL 字节的行识别，之后的行沿用
+#[derive(Clone, Copy, PartialEq)]
+enum LineEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// 把一行原始字节解码为字符串：去掉 BOM，识别 UTF-16 行，无效的 UTF-8 以替换字符有损恢复并记录警告
+fn decode_line(mut bytes: &[u8], encoding: &mut LineEncoding) -> String {
+    if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        bytes = rest;
+        *encoding = LineEncoding::Utf8;
+    } else if let Some(rest) = bytes.strip_prefix(b"\xFF\xFE") {
+        bytes = rest;
+        *encoding = LineEncoding::Utf16Le;
+    } else if let Some(rest) = bytes.strip_prefix(b"\xFE\xFF") {
+        bytes = rest;
+        *encoding = LineEncoding::Utf16Be;
+    } else if !bytes.contains(&0) {
+        *encoding = LineEncoding::Utf8;
+    } else if *encoding == LineEncoding::Utf8 {
+        // JSON 文本几乎都是 ASCII，UTF-16LE 的 NUL 字节落在奇数位置，UTF-16BE 落在偶数位置
+        let odd_zeros = bytes.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+        let even_zeros = bytes.iter().step_by(2).filter(|b| **b == 0).count();
+        *encoding = if odd_zeros >= even_zeros {
+            LineEncoding::Utf16Le
+        } else {
+            LineEncoding::Utf16Be
+        };
+    }
+
+    let text = match *encoding {
+        LineEncoding::Utf8 => match std::str::from_utf8(bytes) {
+            Ok(text) => text.to_string(),
+            Err(e) => {
+                warn!("请求不是有效的 UTF-8（{}），已按有损方式恢复", e);
+                String::from_utf8_lossy(bytes).into_owned()
             }
-            "notifications/initialized" => {
-                eprintln!("客户端已确认初始化");
-                None
+        },
+        utf16 => {
+            // 按 `\n` 切分 UTF-16 流时，换行符的另一半 NUL 字节会留在相邻的行中
+            if bytes.len() % 2 == 1 {
+                bytes = match utf16 {
+                    LineEncoding::Utf16Le => bytes.strip_prefix(&[0]).unwrap_or(bytes),
+                    _ => bytes.strip_suffix(&[0]).unwrap_or(bytes),
+                };
             }
-            "tools/list" => {
-                let config = CONFIG.lock().unwrap();
-                let format_hint = config.commit_format.join("\n");
-                let extra_constraints_hint = config
-
-                    .extra_constraints
-                    .iter()
-                    .map(|c| format!("- {}", c))
-                    .collect::<Vec<_>>()
-                    .join("\n");
-
-                let tools = vec![
-
-                    Tool {
-                        name: "check_files_status".to_string(),
-                        description: "检查当前 git 状态，包括已暂存和未暂存的文件，并根据状态返回下一步工作流建议。".to_string(),
-                        input_schema: json!({
-                            "type": "object",
-                            "properties": {}
-                        }),
-                    },
-                    Tool {
-                        name: "stage_files".to_string(),
-                        description: "将指定的文件添加到 Git 暂存区。".to_string(),
-                        input_schema: json!({
-                            "type": "object",
-                            "properties": {
-                                "paths": {
-                                    "type": "array",
-                                    "items": { "type": "string" },
-                                    "description": "要暂存的文件路径列表"
-                                }
-                            },
-                            "required": ["paths"]
-                        }),
-                    },
-                    Tool {
-                        name: "get_staged_diff".to_string(),
-
-                        description: format!(
-                            "获取当前 git 暂存区的变更内容 (git diff --staged)。\n\n\
-                            ### 工作流要求：\n\
-                            1. 生成提交信息：根据变更内容总结出一个提交信息草稿。\n\
-                            2. 处理 PMS 单号：\n\
-                               - 如果无法确定单号，**必须**询问用户提供。\n\
-                               - 如果用户提供了单号，将其填入提交信息。\n\
-                               - 如果用户明确表示没有单号，**必须从最终提交信息中删除整个 PMS 行**。\n\
-                            3. 处理 Issue 单号：\n\
-                               - 如果无法确定单号，**必须**询问用户提供。\n\
-                               - 如果用户提供了单号，将其填入提交信息。\n\
-                               - 如果用户明确表示没有单号，**必须从最终提交信息中删除整个 Issue 行**。\n\
-                            4. 用户预览与修改：展示草稿，询问用户确认。\n\
-                            5. 严禁直接提交：必须得到用户明确确认后才能执行 execute_commit。\n\n\
-                            ### 提交格式要求：\n{}\n\n\
-                            ### 额外约束：\n{}",
-
-                            format_hint,
-                            extra_constraints_hint
-                        ),
-
-                        input_schema: json!({
-                            "type": "object",
-                            "properties": {}
-                        }),
-                    },
+            let units = bytes.chunks(2).map(|pair| match (utf16, pair) {
+                (LineEncoding::Utf16Le, [lo, hi]) => u16::from_le_bytes([*lo, *hi]),
+                (_, [hi, lo]) => u16::from_be_bytes([*hi, *lo]),
+                (_, [single]) => u16::from(*single),
+                _ => 0,
+            });
+            let mut lossy = false;
+            let text: String = char::decode_utf16(units)
+                .map(|c| {
+                    c.unwrap_or_else(|_| {
+                        lossy = true;
+                        char::REPLACEMENT_CHARACTER
+                    })
+                })
+                .collect();
+            if lossy {
+                warn!("UTF-16 请求中包含无效的字符，已按有损方式恢复");
+            }
+            text
+        }
+    };
 
+    text.trim_start_matches('\u{feff}')
+        .trim_end_matches(['\r', '\0'])
+        .to_string()
+}
 
-                    Tool {
-                        name: "execute_commit".to_string(),
-                        description: "执行提交。请在用户确认了你总结的提交信息后再调用此工具。".to_string(),
-                        input_schema: json!({
-                            "type": "object",
-                            "properties": {
-                                "message": { "type": "string", "description": "提交信息" }
-                            },
-                            "required": ["message"]
-                        }),
-                    },
-                ];
-                Some(json!({ "tools": tools }))
+/// 读取线程交给处理循环的内容
+enum Incoming {
+    /// 待处理的 JSON-RPC 消息
+    Message(serde_json::Value),
+    /// 已生成好的响应，如解析失败的错误
+    Reply(serde_json::Value),
+}
+
+/// 在单独的线程中逐行读取并解析消息；`notifications/cancelled` 不排队，立即生效，
+/// 以便取消正在处理的请求
+fn read_messages(reader: impl BufRead, sender: mpsc::Sender<Incoming>) {
+    let mut lines = reader.split(b'\n');
+    let mut encoding = LineEncoding::Utf8;
+
+    while let Some(Ok(bytes)) = lines.next() {
+        // UTF-16LE 流末尾换行符剩下的 NUL 字节
+        if !bytes.is_empty() && bytes.iter().all(|b| *b == 0) {
+            continue;
+        }
+        let line = decode_line(&bytes, &mut encoding);
+        if server::echo_messages() {
+            trace!("收到请求: {}", line);
+        }
+        let incoming = match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(message) if message["method"] == "notifications/cancelled" => {
+                server::dispatch_message(message);
+                continue;
             }
-            "tools/call" => {
-                let params: CallToolParams =
-                    serde_json::from_value(request.params.clone().unwrap_or_default())?;
-                let tool_result = match params.name.as_str() {
-                    "check_files_status" => match GitHandler::check_files_status() {
-                        Ok((has_staged, unstaged_files)) => {
-                            let text = match (has_staged, unstaged_files.is_empty()) {
-                                (false, true) => "当前没有已暂存或未暂存的文件。请先进行一些修改后再尝试提交。".to_string(),
-                                (false, false) => format!(
-                                    "未暂存的文件：\n{}\n\n工作流提醒：\n请向用户展示上述文件列表。**必须**请用户确认哪些文件需要被暂存（git add）。只有在用户明确指定文件后，才可调用 `stage_files`。",
-                                    unstaged_files.join("\n")
-                                ),
-                                (true, false) => format!(
-                                    "已暂存的文件存在。未暂存的文件：\n{}\n\n工作流提醒：\n1. 已有已暂存的文件，可以直接进行提交。\n2. 询问用户是否需要暂存未暂存的文件。\n3. 如果用户不需暂存更多文件，直接调用 `get_staged_diff`。\n4. 如果用户需要暂存更多文件，调用 `stage_files`。",
-                                    unstaged_files.join("\n")
-                                ),
-                                (true, true) => "已暂存的文件存在，且没有未暂存的文件。请直接调用 `get_staged_diff` 获取变更差异并生成提交信息草稿。".to_string(),
-                            };
-
-                            json!({ "content": [{ "type": "text", "text": text }] })
-                        }
-
-                        Err(e) => {
-                            json!({ "isError": true, "content": [{ "type": "text", "text": e.to_string() }] })
-                        }
-                    },
-                    "stage_files" => {
-                        let arguments = params.arguments.as_ref();
-                        let paths = arguments
-                            .and_then(|a| a["paths"].as_array())
-                            .map(|arr| {
-                                arr.iter()
-                                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                                    .collect::<Vec<String>>()
-                            })
-                            .unwrap_or_default();
-                        match GitHandler::stage_files(paths) {
-                            Ok(_) => json!({ "content": [{ "type": "text", "text": "文件已成功暂存。\n\n提示：现在请使用 `get_staged_diff` 获取变更差异并生成提交信息草稿。" }] }),
-                            Err(e) => {
-                                json!({ "isError": true, "content": [{ "type": "text", "text": e.to_string() }] })
-                            }
-                        }
-
-                    }
-                    "get_staged_diff" => match GitHandler::get_staged_diff() {
-                        Ok(diff) => {
-                            let text = format!(
-                                "{}\n\n工作流提醒：\n1. 请根据上述差异总结一个提交信息草稿。\n2. **必须**询问用户确认 PMS 单号（格式如 BUG-123 或 TASK-456）。\n3. **必须**询问用户确认 GitHub Issue 号码（格式如 #123）。\n4. 展示最终提交信息并请求用户明确确认。\n5. **重要**：如果用户对信息进行了修改或提出了反馈，必须重新展示完整的修改后信息并再次请求确认。\n6. 只有在用户对最终展示的信息表示明确认可（如\"可以提交\"）后，才可调用 `execute_commit`。",
-                                diff
-                            );
-                            json!({ "content": [{ "type": "text", "text": text }] })
-                        }
-
-
-                        Err(e) => {
-                            json!({ "isError": true, "content": [{ "type": "text", "text": e.to_string() }] })
-                        }
-                    },
-                    "execute_commit" => {
-                        let arguments = params.arguments.as_ref();
-                        let msg = arguments.and_then(|a| a["message"].as_str()).unwrap_or("");
-                        match GitHandler::commit(msg) {
-                            Ok(res) => json!({ "content": [{ "type": "text", "text": res }] }),
-                            Err(e) => {
-                                json!({ "isError": true, "content": [{ "type": "text", "text": e.to_string() }] })
-                            }
-                        }
-                    }
-                    _ => {
-                        json!({ "isError": true, "content": [{ "type": "text", "text": "未知工具" }] })
-                    }
-                };
-                Some(tool_result)
+            Ok(message) => Incoming::Message(message),
+            Err(e) => {
+                warn!("JSON 解析失败: {}", e);
+                Incoming::Reply(server::parse_error_response(&line, &e))
             }
-            _ => {
-                if is_notification {
-                    None
-                } else {
-                    Some(json!({ "error": { "code": -32601, "message": "Method not found" } }))
-                }
+        };
+        if sender.send(incoming).is_err() {
+            break;
+        }
+    }
+}
+
+/// 逐行读取 JSON-RPC 消息并把响应与通知写回，直到输入结束或收到 exit 通知；
+/// 读取在单独的线程中进行，请求处理期间仍能收到取消通知
+fn run(reader: impl BufRead + Send + 'static, mut writer: impl Write) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    // 收到 exit 后不等待读取线程，它可能仍阻塞在读取上
+    std::thread::spawn(move || read_messages(reader, sender));
+
+    for incoming in receiver {
+        let message = match incoming {
+            Incoming::Message(message) => message,
+            Incoming::Reply(reply) => {
+                let output = serde_json::to_string(&reply)?;
+                writeln!(writer, "{}", output)?;
+                writer.flush()?;
+                continue;
             }
         };
 
-        if let (Some(payload), Some(id)) = (response_payload, request.id) {
-            let mut response_obj = serde_json::Map::new();
-            response_obj.insert("jsonrpc".to_string(), json!("2.0"));
-            response_obj.insert("id".to_string(), id);
+        if let Some(response) = server::dispatch_message(message) {
+            let output = serde_json::to_string(&response)?;
+
+            writeln!(writer, "{}", output)?;
+            writer.flush()?;
+            if server::echo_messages() {
+                trace!("发送响应: {}", output);
+            }
+        }
 
-            if let Some(error) = payload.get("error") {
-                response_obj.insert("error".to_string(), error.clone());
-            } else {
-                response_obj.insert("result".to_string(), payload);
+        for notification in server::take_notifications() {
+            let output = serde_json::to_string(&notification)?;
+            writeln!(writer, "{}", output)?;
+            writer.flush()?;
+            if server::echo_messages() {
+                trace!("发送通知: {}", output);
             }
+        }
 
-            let output = serde_json::to_string(&response_obj)?;
-            println!("{}", output);
-            io::stdout().flush()?;
-            eprintln!("发送响应: {}", output);
+        if server::exit_requested() {
+            debug!("收到 exit 通知，退出");
+            break;
         }
     }
 
+    writer.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempRepo;
+    use serde_json::{json, Value};
+    use std::io::Cursor;
+    use std::sync::Mutex;
+
+    /// `run` 使用进程内共享的服务端状态，测试之间串行执行
+    static SERVER: Mutex<()> = Mutex::new(());
+
+    /// 把原始字节作为输入流交给 `run`，返回除服务端通知以外的各行响应
+    fn drive_raw(input: Vec<u8>) -> Vec<Value> {
+        let _server = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+        let _env = crate::test_support::env_lock();
+        let mut output = Vec::new();
+        run(Cursor::new(input), &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .filter(|message| message.get("method").is_none())
+            .collect()
+    }
+
+    /// 每条消息占一行
+    fn drive(messages: &[Value]) -> Vec<Value> {
+        let input: String = messages.iter().map(|m| format!("{}\n", m)).collect();
+        drive_raw(input.into_bytes())
+    }
+
+    #[test]
+    fn malformed_tools_call_reports_invalid_params_and_continues() {
+        let responses = drive(&[
+            json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {"arguments": {}}}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "ping"}),
+        ]);
+
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["error"]["code"], -32602);
+        assert_eq!(responses[1]["id"], 2);
+        assert_eq!(responses[1]["result"], json!({}));
+    }
+
+    fn initialize(repo: &TempRepo) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": "initialize",
+            "params": {"options": {"repoPath": repo.path_str()}}
+        })
+    }
+
+    fn call(id: u64, name: &str, arguments: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "tools/call",
+            "params": {"name": name, "arguments": arguments}
+        })
+    }
+
+    /// 工具结果中所有文本内容块拼接后的文本
+    fn tool_text(response: &Value) -> String {
+        response["result"]["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|block| block["text"].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn initialize_list_and_call_tools_end_to_end() {
+        let repo = TempRepo::new();
+        repo.write("hello.txt", "hello world\n");
+
+        let responses = drive(&[
+            initialize(&repo),
+            json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+            json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}),
+            call(2, "stage_files", json!({"paths": ["hello.txt"]})),
+            call(3, "get_staged_diff", json!({})),
+            call(
+                4,
+                "execute_commit",
+                json!({"message": "feat: add hello\n\n添加问候文件。\n\nLog: 添加问候文件"}),
+            ),
+        ]);
+
+        assert_eq!(responses.len(), 5);
+        assert!(responses[0]["result"]["capabilities"]["tools"].is_object());
+        let tools: Vec<&str> = responses[1]["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|tool| tool["name"].as_str())
+            .collect();
+        for name in ["stage_files", "get_staged_diff", "execute_commit"] {
+            assert!(tools.contains(&name), "{:?}", tools);
+        }
+        assert_ne!(responses[2]["result"]["isError"], true, "{}", responses[2]);
+        assert!(tool_text(&responses[3]).contains("+hello world"));
+        assert_ne!(responses[4]["result"]["isError"], true, "{}", responses[4]);
+
+        let raw = repo.repo();
+        let head = raw.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.summary(), Some("feat: add hello"));
+    }
+
+    #[test]
+    fn request_only_methods_require_an_id() {
+        let responses = drive(&[
+            json!({"jsonrpc": "2.0", "method": "tools/list"}),
+            json!({"jsonrpc": "2.0", "method": "initialize", "params": {}}),
+        ]);
+
+        assert_eq!(responses.len(), 2);
+        for response in &responses {
+            assert_eq!(response["id"], Value::Null);
+            assert_eq!(response["error"]["code"], -32600);
+        }
+    }
+
+    #[test]
+    fn string_and_number_ids_are_echoed_unchanged() {
+        let responses = drive(&[
+            json!({"jsonrpc": "2.0", "id": "req-1", "method": "tools/list"}),
+            json!({"jsonrpc": "2.0", "id": 42, "method": "tools/list"}),
+            json!({"jsonrpc": "2.0", "id": "42", "method": "ping"}),
+        ]);
+
+        assert_eq!(responses[0]["id"], json!("req-1"));
+        assert_eq!(responses[1]["id"], json!(42));
+        assert_eq!(responses[2]["id"], json!("42"));
+        assert!(responses.iter().all(|r| r.get("error").is_none()));
+    }
+
+    fn utf16(text: &str, little_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| {
+                if little_endian {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decode_line_strips_bom_and_reads_utf16() {
+        let mut encoding = LineEncoding::Utf8;
+        assert_eq!(
+            decode_line(b"\xEF\xBB\xBF{\"a\":1}\r", &mut encoding),
+            "{\"a\":1}"
+        );
+        assert!(encoding == LineEncoding::Utf8);
+
+        let mut le = b"\xFF\xFE".to_vec();
+        le.extend(utf16("{\"a\":\"中\"}", true));
+        assert_eq!(decode_line(&le, &mut encoding), "{\"a\":\"中\"}");
+        assert!(encoding == LineEncoding::Utf16Le);
+        // 后续没有 BOM 的行沿用已识别的编码，并去掉上一行换行符留下的 NUL 字节
+        let mut next = vec![0];
+        next.extend(utf16("{}", true));
+        assert_eq!(decode_line(&next, &mut encoding), "{}");
+
+        let mut encoding = LineEncoding::Utf8;
+        let mut be = b"\xFE\xFF".to_vec();
+        be.extend(utf16("{\"b\":2}", false));
+        assert_eq!(decode_line(&be, &mut encoding), "{\"b\":2}");
+        assert!(encoding == LineEncoding::Utf16Be);
+
+        let mut encoding = LineEncoding::Utf8;
+        assert_eq!(decode_line(&utf16("{}", false), &mut encoding), "{}");
+        assert!(encoding == LineEncoding::Utf16Be);
+    }
+
+    #[test]
+    fn invalid_utf8_is_recovered_lossily() {
+        let mut encoding = LineEncoding::Utf8;
+        assert_eq!(
+            decode_line(b"{\"a\":\"\xFF\"}", &mut encoding),
+            "{\"a\":\"\u{FFFD}\"}"
+        );
+    }
+
+    #[test]
+    fn bom_prefixed_initialize_is_answered() {
+        let mut input = b"\xEF\xBB\xBF".to_vec();
+        input.extend(
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}})
+                .to_string()
+                .into_bytes(),
+        );
+        input.extend(b"\r\n");
+        let responses = drive_raw(input);
+
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0]["id"], 1);
+        assert!(responses[0]["result"]["serverInfo"].is_object());
+    }
+
+    #[test]
+    fn malformed_and_invalid_requests_get_error_responses() {
+        let mut input = String::from("{\"jsonrpc\": \"2.0\", \"id\": 7, \"method\": \"ping\",\n");
+        input.push_str("not json at all\n");
+        input.push_str("{\"jsonrpc\": \"2.0\", \"id\": 8, \"method\": 42}\n");
+        input.push_str("{\"jsonrpc\": \"1.0\", \"id\": \"nine\", \"method\": \"ping\"}\n");
+        let responses = drive_raw(input.into_bytes());
+
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[0]["id"], 7);
+        assert_eq!(responses[0]["error"]["code"], -32700);
+        assert_eq!(responses[1]["id"], Value::Null);
+        assert_eq!(responses[1]["error"]["code"], -32700);
+        assert_eq!(responses[2]["id"], 8);
+        assert_eq!(responses[2]["error"]["code"], -32600);
+        assert_eq!(responses[3]["id"], "nine");
+        assert_eq!(responses[3]["error"]["code"], -32600);
+    }
+
+    #[test]
+    fn templates_missing_markers_and_unknown_default_are_rejected() {
+        let repo = TempRepo::new();
+        let responses = drive(&[
+            json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "initialize",
+                "params": {"options": {
+                    "repoPath": repo.path_str(),
+                    "templates": {
+                        "short": "<type>: <english description> Log: [short description]",
+                        "broken": ["<type>: <english description>"]
+                    },
+                    "defaultTemplate": "missing"
+                }}
+            }),
+            call(1, "get_staged_diff", json!({"template": "broken"})),
+        ]);
+
+        let info = &responses[0]["result"]["serverInfo"];
+        assert_eq!(info["templateValidation"]["short"]["accepted"], true);
+        assert_eq!(info["templateValidation"]["broken"]["accepted"], false);
+        assert_eq!(
+            info["templateValidation"]["broken"]["missingMarkers"],
+            json!(["Log:"])
+        );
+        assert_eq!(info["defaultTemplateValidation"]["accepted"], false);
+        let available = info["defaultTemplateValidation"]["availableTemplates"]
+            .as_array()
+            .unwrap();
+        assert!(available.contains(&json!("short")), "{:?}", available);
+        assert!(!available.contains(&json!("broken")), "{:?}", available);
+
+        assert_eq!(responses[1]["result"]["isError"], true);
+        assert_eq!(responses[1]["result"]["code"], "INVALID_ARGUMENT");
+        assert!(tool_text(&responses[1]).contains("broken"));
+    }
+}
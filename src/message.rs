@@ -0,0 +1,410 @@
+use regex::Regex;
+use serde::Serialize;
+
+lazy_static::lazy_static! {
+    static ref SUBJECT_PATTERN: Regex = Regex::new(r"^[a-zA-Z]+(\([^()]+\))?!?: \S").unwrap();
+    /// 列表项：`- `、`* `、`+ ` 或 `1. ` 开头
+    static ref BULLET_PATTERN: Regex = Regex::new(r"^\s*([-*+]|\d+[.)])\s").unwrap();
+    /// `Log:`、`PMS:`、`Co-authored-by:` 之类的 trailer 行
+    static ref TRAILER_PATTERN: Regex = Regex::new(r"^[A-Za-z][A-Za-z-]*:(\s|$)").unwrap();
+    /// commitlint 解析的标题：`type(scope)!: subject`
+    static ref HEADER_PATTERN: Regex =
+        Regex::new(r"^(?P<type>[^()!:\s]*)(\([^()]*\))?!?: (?P<subject>.*)$").unwrap();
+}
+
+/// 标题行允许的最大字符数
+const SUBJECT_LINE_LIMIT: usize = 72;
+
+/// 提交格式中出现这些行时，提交信息必须包含对应的行
+const REQUIRED_TRAILERS: &[&str] = &["Log", "Influence"];
+
+/// 自定义提交格式必须包含的占位符，缺少时无法生成符合 `validate` 检查的提交信息
+const REQUIRED_FORMAT_MARKERS: &[&str] = &["<type>", "<english description>", "Log:"];
+
+/// `@commitlint/config-conventional` 允许的提交类型
+pub const CONVENTIONAL_TYPES: &[&str] = &[
+    "build", "chore", "ci", "docs", "feat", "fix", "perf", "refactor", "revert", "style", "test",
+];
+
+/// `@commitlint/config-conventional` 的标题最大长度
+pub const DEFAULT_HEADER_MAX_LENGTH: usize = 100;
+
+/// `lint` 检查使用的规则参数
+#[derive(Debug, Clone)]
+pub struct LintRules {
+    pub allowed_types: Vec<String>,
+    pub header_max_length: usize,
+}
+
+impl Default for LintRules {
+    fn default() -> Self {
+        LintRules {
+            allowed_types: CONVENTIONAL_TYPES.iter().map(|t| t.to_string()).collect(),
+            header_max_length: DEFAULT_HEADER_MAX_LENGTH,
+        }
+    }
+}
+
+/// 一条 commitlint 规则的检查结果，`severity` 为 `error` 或 `warning`
+#[derive(Debug, Serialize)]
+pub struct LintIssue {
+    pub rule: &'static str,
+    pub severity: &'static str,
+    pub message: String,
+}
+
+impl LintIssue {
+    fn error(rule: &'static str, message: String) -> Self {
+        LintIssue {
+            rule,
+            severity: "error",
+            message,
+        }
+    }
+
+    fn warning(rule: &'static str, message: String) -> Self {
+        LintIssue {
+            rule,
+            severity: "warning",
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    /// 违规所在行号（从 1 开始），针对整条信息的问题为 `None`
+    pub line: Option<usize>,
+    pub reason: String,
+}
+
+impl Violation {
+    fn at(line: usize, reason: String) -> Self {
+        Violation {
+            line: Some(line),
+            reason,
+        }
+    }
+}
+
+/// 提交格式的第一行包含 `<type>` 时，标题必须是 conventional commit 格式
+pub fn expects_type(commit_format: &[String]) -> bool {
+    commit_format
+        .first()
+        .map(|l| l.contains("<type>"))
+        .unwrap_or(false)
+}
+
+/// 检查标题行的长度，以及在 `expects_type` 时是否符合 `<type>[scope]: description` 格式
+pub fn subject_violations(subject: &str, expects_type: bool) -> Vec<String> {
+    let mut reasons = Vec::new();
+    let width = subject.chars().count();
+    if width > SUBJECT_LINE_LIMIT {
+        reasons.push(format!(
+            "标题有 {} 个字符，超过了 {} 个字符的限制。",
+            width, SUBJECT_LINE_LIMIT
+        ));
+    }
+    if expects_type && !SUBJECT_PATTERN.is_match(subject) {
+        reasons.push("标题不符合 `<type>[scope]: description` 格式。".to_string());
+    }
+    reasons
+}
+
+/// 按配置的提交格式检查提交信息，返回所有违规项
+pub fn validate(message: &str, commit_format: &[String], body_line_limit: usize) -> Vec<Violation> {
+    let lines: Vec<&str> = message.trim_end().lines().collect();
+    let Some(subject) = lines.first() else {
+        return vec![Violation::at(1, "提交信息为空。".to_string())];
+    };
+
+    let mut violations = Vec::new();
+
+    for reason in subject_violations(subject, expects_type(commit_format)) {
+        violations.push(Violation::at(1, reason));
+    }
+
+    if lines.get(1).is_some_and(|l| !l.trim().is_empty()) {
+        violations.push(Violation::at(2, "标题与正文之间必须空一行。".to_string()));
+    }
+
+    for (idx, line) in lines.iter().enumerate().skip(1) {
+        let width = line.chars().count();
+        if width > body_line_limit {
+            violations.push(Violation::at(
+                idx + 1,
+                format!(
+                    "该行有 {} 个字符，超过了 {} 个字符的限制。",
+                    width, body_line_limit
+                ),
+            ));
+        }
+    }
+
+    for key in REQUIRED_TRAILERS {
+        let prefix = format!("{}:", key);
+        let required = commit_format.iter().any(|l| l.starts_with(&prefix));
+        let present = lines.iter().any(|l| l.trim_start().starts_with(&prefix));
+        if required && !present {
+            violations.push(Violation {
+                line: None,
+                reason: format!("缺少 `{}` 行。", prefix),
+            });
+        }
+    }
+
+    violations
+}
+
+/// 按 `@commitlint/config-conventional` 的规则检查提交信息，规则名与严重程度与 commitlint 一致
+pub fn lint(message: &str, rules: &LintRules) -> Vec<LintIssue> {
+    let lines: Vec<&str> = message.trim_end().lines().collect();
+    let header = lines.first().copied().unwrap_or_default();
+    let mut issues = Vec::new();
+
+    let width = header.chars().count();
+    if width > rules.header_max_length {
+        issues.push(LintIssue::error(
+            "header-max-length",
+            format!(
+                "标题有 {} 个字符，超过了 {} 个字符的限制。",
+                width, rules.header_max_length
+            ),
+        ));
+    }
+
+    match HEADER_PATTERN.captures(header) {
+        None => {
+            issues.push(LintIssue::error(
+                "type-empty",
+                "无法从标题中解析出类型，标题应为 `<type>[scope]: <subject>` 格式。".to_string(),
+            ));
+            issues.push(LintIssue::error(
+                "subject-empty",
+                "无法从标题中解析出主题。".to_string(),
+            ));
+        }
+        Some(caps) => {
+            let commit_type = &caps["type"];
+            if commit_type.is_empty() {
+                issues.push(LintIssue::error("type-empty", "类型不能为空。".to_string()));
+            } else {
+                if commit_type != commit_type.to_lowercase() {
+                    issues.push(LintIssue::error(
+                        "type-case",
+                        format!("类型 `{}` 必须为小写。", commit_type),
+                    ));
+                }
+                if !rules
+                    .allowed_types
+                    .iter()
+                    .any(|t| t.as_str() == commit_type.to_lowercase())
+                {
+                    issues.push(LintIssue::error(
+                        "type-enum",
+                        format!(
+                            "类型 `{}` 不在允许的类型中：{}。",
+                            commit_type,
+                            rules.allowed_types.join(", ")
+                        ),
+                    ));
+                }
+            }
+
+            let subject = caps["subject"].trim();
+            if subject.is_empty() {
+                issues.push(LintIssue::error(
+                    "subject-empty",
+                    "主题不能为空。".to_string(),
+                ));
+            } else {
+                // commitlint 禁止 sentence-case、start-case、pascal-case 与 upper-case，即不能以大写字母开头
+                if subject.starts_with(char::is_uppercase) {
+                    issues.push(LintIssue::error(
+                        "subject-case",
+                        "主题不能以大写字母开头，请使用小写。".to_string(),
+                    ));
+                }
+                if subject.ends_with('.') {
+                    issues.push(LintIssue::error(
+                        "subject-full-stop",
+                        "主题末尾不能有句号。".to_string(),
+                    ));
+                }
+            }
+        }
+    }
+
+    if lines.get(1).is_some_and(|l| !l.trim().is_empty()) {
+        issues.push(LintIssue::warning(
+            "body-leading-blank",
+            "正文前必须空一行。".to_string(),
+        ));
+    }
+
+    let is_footer = |line: &str| {
+        is_trailer(line)
+            || line.starts_with("BREAKING CHANGE:")
+            || line.starts_with("BREAKING-CHANGE:")
+    };
+    // 紧接在正文普通行之后的第一个 footer 行说明缺少空行；标题后的第二行已由 body-leading-blank 报告
+    if let Some(idx) = (2..lines.len()).find(|&i| {
+        is_footer(lines[i]) && !lines[i - 1].trim().is_empty() && !is_footer(lines[i - 1])
+    }) {
+        issues.push(LintIssue::warning(
+            "footer-leading-blank",
+            format!("第 {} 行的 footer 前必须空一行。", idx + 1),
+        ));
+    }
+
+    issues
+}
+
+/// 返回提交格式中缺少的必需占位符，为空表示格式可用
+pub fn missing_format_markers(commit_format: &[String]) -> Vec<&'static str> {
+    REQUIRED_FORMAT_MARKERS
+        .iter()
+        .copied()
+        .filter(|marker| !commit_format.iter().any(|l| l.contains(marker)))
+        .collect()
+}
+
+/// 判断一行是否为 `Key: value` 形式的 trailer
+pub fn is_trailer(line: &str) -> bool {
+    TRAILER_PATTERN.is_match(line)
+}
+
+/// 把正文中的普通段落重新折行到 `limit` 个字符以内；标题、列表项、trailer 与缩进的行保持原样，
+/// 段落之间的空行保留。只在空白处或中日韩字符之间断行，不会拆开单词
+pub fn wrap_body(message: &str, limit: usize) -> String {
+    let mut lines = message.lines();
+    let Some(subject) = lines.next() else {
+        return message.to_string();
+    };
+
+    let mut output = vec![subject.to_string()];
+    let mut paragraph: Vec<&str> = Vec::new();
+    for line in lines {
+        let keep = line.trim().is_empty()
+            || line.starts_with(char::is_whitespace)
+            || BULLET_PATTERN.is_match(line)
+            || is_trailer(line);
+        if keep {
+            output.extend(wrap_paragraph(&paragraph, limit));
+            paragraph.clear();
+            output.push(line.to_string());
+        } else {
+            paragraph.push(line);
+        }
+    }
+    output.extend(wrap_paragraph(&paragraph, limit));
+
+    let mut wrapped = output.join("\n");
+    if message.ends_with('\n') {
+        wrapped.push('\n');
+    }
+    wrapped
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{2E80}'..='\u{9FFF}' | '\u{AC00}'..='\u{D7AF}' | '\u{F900}'..='\u{FAFF}' | '\u{FF00}'..='\u{FFEF}')
+}
+
+/// 贪心折行：英文按单词断开，中日韩字符之间可以直接断开且连接时不加空格
+fn wrap_paragraph(lines: &[&str], limit: usize) -> Vec<String> {
+    // 每个片段记录与前一片段之间是否需要空格
+    let mut pieces: Vec<(bool, String)> = Vec::new();
+    for word in lines.iter().flat_map(|l| l.split_whitespace()) {
+        let mut first = true;
+        let mut run = String::new();
+        for c in word.chars() {
+            if is_cjk(c) {
+                if !run.is_empty() {
+                    pieces.push((first, std::mem::take(&mut run)));
+                    first = false;
+                }
+                pieces.push((first, c.to_string()));
+                first = false;
+            } else {
+                run.push(c);
+            }
+        }
+        if !run.is_empty() {
+            pieces.push((first, run));
+        }
+    }
+
+    let mut wrapped = Vec::new();
+    let mut current = String::new();
+    let mut width = 0;
+    for (spaced, piece) in pieces {
+        // 两个中日韩字符之间原本的换行或空格不需要保留
+        let spaced = spaced
+            && !(current.chars().last().is_some_and(is_cjk)
+                && piece.chars().next().is_some_and(is_cjk));
+        let piece_width = piece.chars().count();
+        let extra = piece_width + usize::from(spaced);
+        if width > 0 && width + extra > limit {
+            wrapped.push(std::mem::take(&mut current));
+            width = 0;
+        }
+        if width > 0 && spaced {
+            current.push(' ');
+            width += 1;
+        }
+        current.push_str(&piece);
+        width += piece_width;
+    }
+    if !current.is_empty() {
+        wrapped.push(current);
+    }
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_body_only_rewraps_prose_paragraphs() {
+        let message = "feat: a subject line that is longer than the limit\n\
+            \n\
+            This paragraph is long enough that it has to be wrapped onto\n\
+            several lines.\n\
+            \n\
+            - a bullet item that is also longer than the limit stays as is\n\
+            \n\
+            Log: a trailer that is longer than the limit stays as is\n";
+
+        let wrapped = wrap_body(message, 20);
+        assert_eq!(
+            wrapped,
+            "feat: a subject line that is longer than the limit\n\
+            \n\
+            This paragraph is\n\
+            long enough that it\n\
+            has to be wrapped\n\
+            onto several lines.\n\
+            \n\
+            - a bullet item that is also longer than the limit stays as is\n\
+            \n\
+            Log: a trailer that is longer than the limit stays as is\n"
+        );
+    }
+
+    #[test]
+    fn wrap_body_never_splits_words() {
+        let wrapped = wrap_body("fix: x\n\nsee supercalifragilisticexpialidocious now", 10);
+        assert_eq!(
+            wrapped,
+            "fix: x\n\nsee\nsupercalifragilisticexpialidocious\nnow"
+        );
+    }
+
+    #[test]
+    fn wrap_body_breaks_between_cjk_characters() {
+        let wrapped = wrap_body("fix: x\n\n修复暂存区差异\n截断后的统计", 6);
+        assert_eq!(wrapped, "fix: x\n\n修复暂存区差\n异截断后的统\n计");
+    }
+}
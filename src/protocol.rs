@@ -3,6 +3,9 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InitializeParams {
+    /// 客户端请求的协议版本
+    #[serde(default, rename = "protocolVersion")]
+    pub protocol_version: Option<String>,
     #[serde(default)]
     pub options: Option<serde_json::Value>,
     #[serde(flatten)]
@@ -31,3 +34,15 @@ pub struct Tool {
     pub input_schema: serde_json::Value,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
+    pub mime_type: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReadResourceParams {
+    pub uri: String,
+}
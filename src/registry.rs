@@ -0,0 +1,43 @@
+use crate::protocol::Tool;
+use anyhow::Result;
+use serde_json::Value;
+
+/// 工具处理函数，接收 `tools/call` 的参数，返回工具结果
+type Handler = Box<dyn Fn(&Value) -> Result<Value> + Send + Sync>;
+
+struct Entry {
+    tool: Tool,
+    handler: Handler,
+}
+
+/// 工具元数据与处理函数的登记表，按注册顺序列出工具
+#[derive(Default)]
+pub struct ToolRegistry {
+    entries: Vec<Entry>,
+}
+
+impl ToolRegistry {
+    pub fn register(
+        &mut self,
+        tool: Tool,
+        handler: impl Fn(&Value) -> Result<Value> + Send + Sync + 'static,
+    ) {
+        self.entries.push(Entry {
+            tool,
+            handler: Box::new(handler),
+        });
+    }
+
+    /// 所有工具的元数据，供 `tools/list` 使用
+    pub fn tools(&self) -> Vec<&Tool> {
+        self.entries.iter().map(|e| &e.tool).collect()
+    }
+
+    /// 调用指定名称的工具，未注册时返回 `None`
+    pub fn call(&self, name: &str, args: &Value) -> Option<Result<Value>> {
+        self.entries
+            .iter()
+            .find(|e| e.tool.name == name)
+            .map(|e| (e.handler)(args))
+    }
+}
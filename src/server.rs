@@ -0,0 +1,2810 @@
+use crate::cancel;
+use crate::config::{self, PROJECT_CONFIG_FILE};
+use crate::git::{
+    parse_commit_date, CommitOptions, CommitResult, DateFormat, DiffFormat, DiffSettings, GitError,
+    GitHandler, ALLOWED_CONFIG_KEYS, DEFAULT_INDEX_LOCK_RETRIES,
+};
+use crate::message;
+use crate::protocol::{
+    CallToolParams, InitializeParams, JsonRpcRequest, ReadResourceParams, Resource, Tool,
+};
+use crate::registry::ToolRegistry;
+use anyhow::Result;
+use regex::Regex;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tracing::{debug, info, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// 支持的 MCP 协议版本，按从新到旧排列
+const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// 未指定 `--repo` 与 `repoPath` 时读取的仓库路径环境变量
+const REPO_ENV_VAR: &str = "GIT_SUMMARIZER_REPO";
+
+const DEFAULT_BODY_LINE_LIMIT: usize = 80;
+const MAX_CONTEXT_LINES: u64 = 100;
+/// `renameThreshold` 是相似度百分比
+const MAX_RENAME_THRESHOLD: u64 = 100;
+
+// 默认格式中与中文相关的行，`require_chinese_body` 关闭时替换为下面的英文版本
+const CHINESE_BODY_LINE: &str = "[Chinese body]";
+const CHINESE_LOG_LINE: &str = "Log: [short description of the change use chinese language]";
+const CHINESE_INFLUENCE_LINE: &str =
+    "Influence: Explain in Chinese the potential impact of this submission.";
+const BILINGUAL_CONSTRAINT: &str = "中英文 Body 必须成对出现，不得只写其中一个。";
+const ENGLISH_LOG_LINE: &str = "Log: [short description of the change]";
+const ENGLISH_INFLUENCE_LINE: &str = "Influence: Explain the potential impact of this submission.";
+
+struct ServerConfig {
+    commit_format: Vec<String>,
+    extra_constraints: Vec<String>,
+    body_line_limit: usize,
+    /// 为 false 时从默认格式中去掉中文 Body 及中文 Log/Influence 的要求
+    require_chinese_body: bool,
+    /// 在 `get_staged_diff` 的工具说明中附上按提交格式填写的示例
+    show_example: bool,
+    diff_settings: DiffSettings,
+    /// 命令行参数 `--repo` 指定的仓库路径，优先于 `repo_path`
+    cli_repo_path: Option<String>,
+    /// `initialize` 选项 `repoPath` 指定的仓库路径
+    repo_path: Option<String>,
+    signing_key: Option<String>,
+    /// 具名的提交格式模板，可在 `get_staged_diff` 中通过 `template` 选择
+    templates: BTreeMap<String, Vec<String>>,
+    /// 未指定 `template` 时使用的模板名
+    default_template: Option<String>,
+    /// `lint_commit_message` 使用的 commitlint 规则参数
+    lint_rules: message::LintRules,
+    /// 索引被其他 git 进程锁定时，提交与暂存操作的重试次数
+    index_lock_retries: u32,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            commit_format: vec![
+                "<type>[optional scope]: <english description>".to_string(),
+                "".to_string(),
+                "[English body]".to_string(),
+                "".to_string(),
+                CHINESE_BODY_LINE.to_string(),
+                "".to_string(),
+                CHINESE_LOG_LINE.to_string(),
+                "PMS: <BUG-number> or <TASK-number> (必须包含 'BUG-' 或 'TASK-' 前缀。如果没有，必须询问用户；若用户明确不提供，则从提交信息中删除此行)".to_string(),
+                "Issue: Fixes #xxx (所修复的bug对应的github issue，其中 \"Fixes #xxx\" 是github关闭issue的规则，此处内容只需要满足github的要求即可。如果没有，必须询问用户；若用户明确不提供，则从提交信息中删除此行)".to_string(),
+                CHINESE_INFLUENCE_LINE.to_string(),
+            ],
+            extra_constraints: vec![
+                BILINGUAL_CONSTRAINT.to_string(),
+            ],
+            body_line_limit: DEFAULT_BODY_LINE_LIMIT,
+            require_chinese_body: true,
+            show_example: false,
+            diff_settings: DiffSettings::default(),
+            cli_repo_path: None,
+            repo_path: None,
+            signing_key: None,
+            templates: BTreeMap::new(),
+            default_template: None,
+            lint_rules: message::LintRules::default(),
+            index_lock_retries: DEFAULT_INDEX_LOCK_RETRIES,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref CONFIG: Mutex<ServerConfig> = Mutex::new(ServerConfig::default());
+
+    /// 从解析失败的原始消息中提取 `"id"` 字段（字符串或整数）
+    static ref RAW_ID_PATTERN: Regex =
+        Regex::new(r#""id"\s*:\s*("(?:[^"\\]|\\.)*"|-?\d+)"#).unwrap();
+
+    /// 整个进程共享的仓库句柄；`Repository` 不是 `Sync`，通过互斥锁串行访问
+    static ref GIT: Mutex<Option<GitHandler>> = Mutex::new(None);
+
+    /// 已构建的工具登记表及构建时的工具说明，说明随配置变化时才重新构建
+    static ref TOOL_REGISTRY: Mutex<Option<(ToolHints, Arc<ToolRegistry>)>> = Mutex::new(None);
+}
+
+/// 按 `--repo`、`repoPath`、`GIT_SUMMARIZER_REPO` 环境变量、`GIT_DIR`/`GIT_WORK_TREE` 环境变量、
+/// 当前目录的优先级确定仓库路径，同时返回路径的来源；路径为 `None` 时由 git 环境变量决定
+fn resolve_repo(config: &ServerConfig) -> (Option<String>, &'static str) {
+    if let Some(path) = &config.cli_repo_path {
+        return (Some(path.clone()), "--repo 参数");
+    }
+    if let Some(path) = &config.repo_path {
+        return (Some(path.clone()), "repoPath 配置项");
+    }
+    match std::env::var(REPO_ENV_VAR) {
+        Ok(path) if !path.is_empty() => (Some(path), "GIT_SUMMARIZER_REPO 环境变量"),
+        _ if GitHandler::env_configured() => (None, "GIT_DIR/GIT_WORK_TREE 环境变量"),
+        _ => (Some(".".to_string()), "当前工作目录"),
+    }
+}
+
+/// 打开 `resolve_repo` 确定的仓库
+fn open_resolved(config: &ServerConfig) -> Result<GitHandler> {
+    let mut handler = match resolve_repo(config) {
+        (Some(path), source) => {
+            info!("仓库路径: {}（来源: {}）", path, source);
+            GitHandler::open(&path)?
+        }
+        (None, source) => {
+            info!("仓库路径由 {} 指定", source);
+            GitHandler::open_from_env()?
+        }
+    };
+    handler.set_index_lock_retries(config.index_lock_retries);
+    Ok(handler)
+}
+
+/// 设置仓库路径，用于命令行参数 `--repo`
+pub fn set_repo_path(path: String) {
+    CONFIG.lock().unwrap().cli_repo_path = Some(path);
+}
+
+/// 按当前配置打开仓库并读取其项目配置，在启动时调用
+pub fn open_repository() -> Result<()> {
+    let mut config = CONFIG.lock().unwrap();
+    let handler = open_resolved(&config)?;
+    apply_repo_config(&mut config, &handler);
+    *GIT.lock().unwrap() = Some(handler);
+    Ok(())
+}
+
+/// 载入仓库自带的配置：先采用 git 的 `commit.template` 作为提交格式，再由项目配置文件覆盖
+fn apply_repo_config(config: &mut ServerConfig, handler: &GitHandler) {
+    match handler.commit_template() {
+        Ok(Some(format)) => {
+            info!("使用 commit.template 作为提交格式");
+            config.commit_format = format;
+        }
+        Ok(None) => {}
+        Err(e) => warn!("读取 commit.template 失败，已忽略: {}", e),
+    }
+    if let Some(root) = handler.workdir() {
+        apply_project_config(config, root);
+    }
+}
+
+/// 用仓库根目录下的 `.git-summarizer.toml` 覆盖配置，文件有误时只记录警告
+fn apply_project_config(config: &mut ServerConfig, root: &Path) {
+    let project = match config::load(root) {
+        Ok(Some(project)) => project,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("{} 有误，已忽略: {}", PROJECT_CONFIG_FILE, e);
+            return;
+        }
+    };
+
+    if let Some(format) = project.commit_format {
+        config.commit_format = format.into_vec();
+    }
+    if let Some(constraints) = project.extra_constraints {
+        config.extra_constraints = constraints;
+    }
+    if let Some(limit) = project.body_line_limit.filter(|l| *l > 0) {
+        config.body_line_limit = limit;
+    }
+    if let Some(templates) = project.templates {
+        config
+            .templates
+            .extend(templates.into_iter().map(|(name, t)| (name, t.into_vec())));
+    }
+    if let Some(name) = project.default_template {
+        config.default_template = Some(name);
+    }
+    if let Some(require) = project.require_chinese_body {
+        config.require_chinese_body = require;
+    }
+    if let Some(show) = project.show_example {
+        config.show_example = show;
+    }
+    if let Some(lines) = project.context_lines {
+        if u64::from(lines) <= MAX_CONTEXT_LINES {
+            config.diff_settings.context_lines = lines;
+        } else {
+            warn!(
+                "context_lines 必须是 0 到 {} 之间的整数，已忽略: {}",
+                MAX_CONTEXT_LINES, lines
+            );
+        }
+    }
+    if let Some(max_bytes) = project.max_diff_bytes {
+        config.diff_settings.max_bytes = max_bytes;
+    }
+    if let Some(max_files) = project.max_files {
+        config.diff_settings.max_files = max_files;
+    }
+    if let Some(chunk_bytes) = project.chunk_bytes {
+        config.diff_settings.chunk_bytes = chunk_bytes;
+    }
+    if let Some(patterns) = project.exclude_patterns {
+        config.diff_settings.exclude_patterns = patterns;
+    }
+    if let Some(threshold) = project.rename_threshold {
+        if u64::from(threshold) <= MAX_RENAME_THRESHOLD {
+            config.diff_settings.rename_threshold = Some(threshold);
+        } else {
+            warn!(
+                "rename_threshold 必须是 0 到 {} 之间的整数，已忽略: {}",
+                MAX_RENAME_THRESHOLD, threshold
+            );
+        }
+    }
+    if let Some(types) = project.lint_types {
+        config.lint_rules.allowed_types = types;
+    }
+    if let Some(max_length) = project.lint_header_max_length.filter(|l| *l > 0) {
+        config.lint_rules.header_max_length = max_length;
+    }
+    info!(
+        "已加载项目配置 {}",
+        root.join(PROJECT_CONFIG_FILE).display()
+    );
+}
+
+/// 借用共享的仓库句柄执行操作，尚未打开时先尝试打开
+fn with_git<T>(f: impl FnOnce(&mut GitHandler) -> Result<T>) -> Result<T> {
+    let mut git = GIT.lock().unwrap();
+    if git.is_none() {
+        // 与 `initialize` 保持先 CONFIG 后 GIT 的加锁顺序
+        drop(git);
+        let handler = open_resolved(&CONFIG.lock().unwrap())?;
+        git = GIT.lock().unwrap();
+        if git.is_none() {
+            *git = Some(handler);
+        }
+    }
+    f(git.as_mut().expect("仓库句柄已打开"))
+}
+
+/// 客户端已发送 `shutdown`，之后应只会收到 `exit`
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// 客户端已发送 `exit` 通知，传输层应结束读取循环
+static EXIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// 设置了 `GIT_SUMMARIZER_QUIET` 时，日志中不回显请求与响应的内容，避免差异泄露到共享日志中
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// 在启动时读取一次 `GIT_SUMMARIZER_QUIET`
+pub fn init_quiet() {
+    let quiet = std::env::var("GIT_SUMMARIZER_QUIET")
+        .is_ok_and(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes" | "on"));
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// 是否在日志中回显请求与响应的内容
+pub fn echo_messages() -> bool {
+    !QUIET.load(Ordering::Relaxed)
+}
+
+/// 客户端已完成初始化握手，此后工具列表变化需要通知客户端
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    /// 等待传输层发送给客户端的通知
+    static ref PENDING_NOTIFICATIONS: Mutex<Vec<serde_json::Value>> = Mutex::new(Vec::new());
+}
+
+/// 取出所有待发送的服务端通知
+pub fn take_notifications() -> Vec<serde_json::Value> {
+    std::mem::take(&mut *PENDING_NOTIFICATIONS.lock().unwrap())
+}
+
+/// 工具说明依赖的配置（如提交格式）变化后调用，通知客户端重新获取工具列表
+fn notify_tools_changed() {
+    if !INITIALIZED.load(Ordering::SeqCst) {
+        return;
+    }
+    debug!("工具列表已变化，通知客户端");
+    PENDING_NOTIFICATIONS.lock().unwrap().push(json!({
+        "jsonrpc": "2.0",
+        "method": "notifications/tools/list_changed"
+    }));
+}
+
+/// 是否已收到 `exit` 通知
+pub fn exit_requested() -> bool {
+    EXIT_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// 运行时可替换的日志过滤器，供 `logging/setLevel` 使用
+pub static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// 将 MCP 日志级别映射为 tracing 的过滤指令
+fn mcp_level_to_filter(level: &str) -> Option<&'static str> {
+    match level {
+        "debug" => Some("debug"),
+        "info" | "notice" => Some("info"),
+        "warning" => Some("warn"),
+        "error" | "critical" | "alert" | "emergency" => Some("error"),
+        _ => None,
+    }
+}
+
+fn call_tool(params: CallToolParams) -> Result<serde_json::Value> {
+    let args = params.arguments.unwrap_or_default();
+    match tool_registry().call(&params.name, &args) {
+        Some(result) => result,
+        None => Ok(tool_error(&GitError::UnknownTool.with("未知工具"))),
+    }
+}
+
+/// 按工具参数调整配置中的差异选项，`format` 取值无效时返回错误
+fn diff_settings_from(args: &serde_json::Value) -> Result<DiffSettings> {
+    let mut diff_settings = CONFIG.lock().unwrap().diff_settings.clone();
+    if let Some(ignore) = args["ignoreWhitespace"].as_bool() {
+        diff_settings.ignore_whitespace = ignore;
+    }
+    if let Some(max_bytes) = args["maxDiffBytes"].as_u64() {
+        diff_settings.max_bytes = max_bytes as usize;
+    }
+    if let Some(max_files) = args["maxFiles"].as_u64() {
+        diff_settings.max_files = max_files as usize;
+    }
+    if let Some(chunk_bytes) = args["chunkBytes"].as_u64() {
+        diff_settings.chunk_bytes = chunk_bytes as usize;
+    }
+    if let Some(word_diff) = args["wordDiff"].as_bool() {
+        diff_settings.word_diff = word_diff;
+    }
+    if let Some(include) = args["includeUntracked"].as_bool() {
+        diff_settings.include_untracked = include;
+    }
+    if let Some(format) = args["format"].as_str() {
+        diff_settings.format = match format {
+            "patch" => DiffFormat::Patch,
+            "json" => DiffFormat::Json,
+            _ => {
+                return Err(GitError::InvalidArgument.with(format!(
+                    "无效的 format `{}`，可选值为 patch、json。",
+                    format
+                )))
+            }
+        };
+    }
+    Ok(diff_settings)
+}
+
+/// 差异类工具共用的 `format` 参数说明
+fn format_property() -> serde_json::Value {
+    json!({ "type": "string", "enum": ["patch", "json"], "description": "输出格式：patch（默认）为 unified diff 文本；json 为按文件列出的结构化 hunk（oldStart、oldLines、newStart、newLines 与 lines），便于客户端自行渲染" })
+}
+
+/// 处理 `tools/call`：参数无法解析时返回 -32602，工具执行出错时返回 -32603，不会中断请求循环
+fn tools_call(params: Option<serde_json::Value>) -> serde_json::Value {
+    match serde_json::from_value::<CallToolParams>(params.unwrap_or_default()) {
+        Ok(params) => match call_tool(params) {
+            Ok(tool_result) => tool_result,
+            Err(e) => json!({ "error": { "code": -32603, "message": e.to_string() } }),
+        },
+        Err(e) => json!({
+            "error": { "code": -32602, "message": format!("Invalid params: {}", e) }
+        }),
+    }
+}
+
+/// 差异与随后的提示：patch 格式合并为一个内容块，JSON 格式把差异单独放在第一个内容块中，
+/// 便于客户端直接解析
+fn diff_content(diff: &str, text: &str, settings: &DiffSettings) -> serde_json::Value {
+    match settings.format {
+        DiffFormat::Patch if settings.chunk_bytes > 0 && diff.len() > settings.chunk_bytes => {
+            let chunks = split_chunks(diff, settings.chunk_bytes);
+            let mut content: Vec<_> = chunks
+                .iter()
+                .map(|chunk| json!({ "type": "text", "text": chunk }))
+                .collect();
+            content.push(json!({
+                "type": "text",
+                "text": format!("（差异较大，已按行拆分为以上 {} 个内容块，请合并阅读。）\n\n{}", chunks.len(), text)
+            }));
+            content.into()
+        }
+        DiffFormat::Patch => json!([{ "type": "text", "text": format!("{}\n\n{}", diff, text) }]),
+        DiffFormat::Json => json!([
+            { "type": "text", "text": diff },
+            { "type": "text", "text": text }
+        ]),
+    }
+}
+
+/// 按行把文本拆分为不超过 `max_bytes` 的片段，片段之间不断开行；单行超过上限时在字符边界处拆开
+fn split_chunks(text: &str, max_bytes: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let (mut start, mut end) = (0, 0);
+    for line in text.split_inclusive('\n') {
+        let line_end = end + line.len();
+        if line_end - start > max_bytes && end > start {
+            chunks.push(&text[start..end]);
+            start = end;
+        }
+        while line_end - start > max_bytes {
+            let mut cut = start + max_bytes;
+            while !text.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            // 上限小于一个字符的宽度时至少保留一个字符
+            if cut == start {
+                cut = start + max_bytes;
+                while !text.is_char_boundary(cut) {
+                    cut += 1;
+                }
+            }
+            chunks.push(&text[start..cut]);
+            start = cut;
+        }
+        end = line_end;
+    }
+    if end > start {
+        chunks.push(&text[start..end]);
+    }
+    chunks
+}
+
+/// 读取 `dateFormat` 参数，未指定时为 `iso`
+fn date_format_from(args: &serde_json::Value) -> Result<DateFormat> {
+    match args["dateFormat"].as_str() {
+        None => Ok(DateFormat::default()),
+        Some(value) => DateFormat::parse(value).ok_or_else(|| {
+            GitError::InvalidArgument.with(format!(
+                "无效的 dateFormat `{}`，可选值为 iso、relative、unix。",
+                value
+            ))
+        }),
+    }
+}
+
+/// 提交记录类工具共用的 `dateFormat` 参数说明
+fn date_format_property() -> serde_json::Value {
+    json!({ "type": "string", "enum": ["iso", "relative", "unix"], "description": "提交时间的格式：iso（默认）为保留提交时区的 RFC 3339，relative 为相对时间（如 3 days ago），unix 为 Unix 时间戳（秒）" })
+}
+
+/// 工具调用失败时的结果；`code` 字段给出可供程序处理的错误类别，未分类的错误为 `GIT_ERROR`
+fn tool_error(error: &anyhow::Error) -> serde_json::Value {
+    let code = GitError::of(error).map_or("GIT_ERROR", GitError::code);
+    json!({
+        "isError": true,
+        "code": code,
+        "content": [{ "type": "text", "text": error.to_string() }]
+    })
+}
+
+/// 从 `execute_commit` 与 `commit_paths` 的参数中读取提交选项
+fn commit_options(args: &serde_json::Value) -> Result<CommitOptions> {
+    let author = match (args["authorName"].as_str(), args["authorEmail"].as_str()) {
+        (Some(name), Some(email)) => Some((name.to_string(), email.to_string())),
+        (None, None) => None,
+        _ => return Err(GitError::InvalidArgument.with("authorName 与 authorEmail 必须同时提供。")),
+    };
+    let date = match args["date"].as_str() {
+        Some(date) => Some(parse_commit_date(date)?),
+        None => None,
+    };
+    let config = CONFIG.lock().unwrap();
+    Ok(CommitOptions {
+        sign: args["sign"].as_bool(),
+        signing_key: config.signing_key.clone(),
+        co_authors: args["coAuthors"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        skip_hooks: args["skipHooks"].as_bool().unwrap_or(false),
+        allow_empty: args["allowEmpty"].as_bool().unwrap_or(false),
+        author,
+        date,
+        strict: args["strict"].as_bool().unwrap_or(true),
+        require_type: message::expects_type(&config.commit_format),
+        signoff: args["signoff"].as_bool().unwrap_or(false),
+    })
+}
+
+/// 创建提交的工具的参数 schema：在 `properties` 之外加上 `commit_options` 读取的全部参数，
+/// 使各工具声明的参数与实际支持的保持一致
+fn commit_schema(properties: serde_json::Value, required: &[&str]) -> serde_json::Value {
+    let mut properties = match properties {
+        serde_json::Value::Object(properties) => properties,
+        _ => serde_json::Map::new(),
+    };
+    let options = json!({
+        "sign": { "type": "boolean", "description": "是否对提交进行 GPG/SSH 签名，默认沿用 git 配置 commit.gpgsign" },
+        "coAuthors": {
+            "type": "array",
+            "items": { "type": "string" },
+            "description": "合作者列表，格式为 `Name <email>`，将以 Co-authored-by trailer 追加到提交信息末尾"
+        },
+        "skipHooks": { "type": "boolean", "description": "跳过 pre-commit 钩子，仅在紧急情况下由用户明确要求时使用" },
+        "allowEmpty": { "type": "boolean", "description": "允许创建没有任何变更的空提交（如用于触发 CI），默认不允许" },
+        "signoff": { "type": "boolean", "description": "以提交者身份在末尾追加 `Signed-off-by: Name <email>` trailer（同 git commit -s），已存在时不重复添加。用于要求 DCO 的项目" },
+        "authorName": { "type": "string", "description": "覆盖作者与提交者姓名，须与 authorEmail 同时提供" },
+        "authorEmail": { "type": "string", "description": "覆盖作者与提交者邮箱，须与 authorName 同时提供" },
+        "date": { "type": "string", "description": "覆盖作者与提交者时间，RFC 3339 格式（如 2024-05-01T12:00:00+08:00）或 Unix 时间戳；省略时使用当前时间" },
+        "strict": { "type": "boolean", "description": "提交前检查标题不超过 72 个字符且符合配置格式要求的 `<type>[scope]: description`，不符合时拒绝提交并返回原因。默认 true，仅在用户明确要求时设为 false" }
+    });
+    if let serde_json::Value::Object(options) = options {
+        properties.extend(options);
+    }
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+/// 提交结果的工具响应：一行摘要加上结构化的提交信息
+fn commit_response(result: Result<CommitResult>) -> Result<serde_json::Value> {
+    Ok(match result {
+        Ok(res) => {
+            let text = if res.detached {
+                format!(
+                    "提交成功（分离 HEAD 状态）: [{}] {}\n\n注意：提交创建于分离 HEAD 状态，位于 {}，不属于任何分支。请提醒用户，如需保留该提交，应基于它创建分支（如 git switch -c <分支名>）。",
+                    res.short_id, res.subject, res.id
+                )
+            } else {
+                format!(
+                    "提交成功: [{} {}] {}",
+                    res.branch, res.short_id, res.subject
+                )
+            };
+            json!({ "content": [
+                { "type": "text", "text": text },
+                { "type": "text", "text": serde_json::to_string(&res)? }
+            ] })
+        }
+        Err(e) => tool_error(&e),
+    })
+}
+
+/// 按 `require_chinese_body` 调整提交格式与约束，只改写默认格式中的中文相关行
+fn effective_format(config: &ServerConfig, commit_format: &[String]) -> (Vec<String>, Vec<String>) {
+    if config.require_chinese_body {
+        return (commit_format.to_vec(), config.extra_constraints.clone());
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+    for line in commit_format {
+        match line.as_str() {
+            CHINESE_BODY_LINE => {
+                // 连同中文 Body 前的空行一起去掉
+                if lines.last().is_some_and(|l| l.is_empty()) {
+                    lines.pop();
+                }
+            }
+            CHINESE_LOG_LINE => lines.push(ENGLISH_LOG_LINE.to_string()),
+            CHINESE_INFLUENCE_LINE => lines.push(ENGLISH_INFLUENCE_LINE.to_string()),
+            _ => lines.push(line.clone()),
+        }
+    }
+    let extra_constraints = config
+        .extra_constraints
+        .iter()
+        .filter(|c| c.as_str() != BILINGUAL_CONSTRAINT)
+        .cloned()
+        .collect();
+    (lines, extra_constraints)
+}
+
+/// 解析 `template` 参数（缺省时使用默认模板），返回模板名及其格式说明与约束
+fn resolve_template(name: Option<&str>) -> Result<Option<(String, String, String)>> {
+    let config = CONFIG.lock().unwrap();
+    let Some(name) = name.or(config.default_template.as_deref()) else {
+        return Ok(None);
+    };
+    let Some(format) = config.templates.get(name) else {
+        let names = config.templates.keys().cloned().collect::<Vec<_>>();
+        return Err(GitError::InvalidArgument.with(format!(
+            "未知的提交模板 `{}`，可用模板: {}",
+            name,
+            if names.is_empty() {
+                "（未配置任何模板）".to_string()
+            } else {
+                names.join(", ")
+            }
+        )));
+    };
+    let (format_hint, extra_constraints_hint) = format_hints(&config, format);
+    Ok(Some((
+        name.to_string(),
+        format_hint,
+        extra_constraints_hint,
+    )))
+}
+
+/// 由配置生成提交格式说明与约束列表（行宽限制位于约束首行）
+fn format_hints(config: &ServerConfig, commit_format: &[String]) -> (String, String) {
+    let (commit_format, extra_constraints) = effective_format(config, commit_format);
+    let format_hint = commit_format.join("\n");
+    let line_limit_constraint =
+        format!("Body 的每一行不得超过 {} 个字符。", config.body_line_limit);
+    let extra_constraints_hint = std::iter::once(&line_limit_constraint)
+        .chain(extra_constraints.iter())
+        .map(|c| format!("- {}", c))
+        .collect::<Vec<_>>()
+        .join("\n");
+    (format_hint, extra_constraints_hint)
+}
+
+/// 按提交格式逐行填入示例内容：默认格式中的各行使用内置的示例，其余行只替换常见的占位符
+fn format_example(commit_format: &[String]) -> String {
+    commit_format
+        .iter()
+        .map(|line| match line.as_str() {
+            "[English body]" => {
+                "Return an empty list instead of panicking when the input is empty.".to_string()
+            }
+            CHINESE_BODY_LINE => "输入为空时返回空列表，而不是直接崩溃。".to_string(),
+            CHINESE_LOG_LINE => "Log: 修复空输入导致解析崩溃的问题".to_string(),
+            ENGLISH_LOG_LINE => "Log: fix parser crash on empty input".to_string(),
+            CHINESE_INFLUENCE_LINE => {
+                "Influence: 仅影响空输入的解析，其他输入的行为不变。".to_string()
+            }
+            ENGLISH_INFLUENCE_LINE => {
+                "Influence: Only parsing of empty input is affected.".to_string()
+            }
+            line if line.starts_with("PMS:") => "PMS: BUG-1234".to_string(),
+            line if line.starts_with("Issue:") => "Issue: Fixes #56".to_string(),
+            line => line
+                .replace("<type>", "fix")
+                .replace("[optional scope]", "(parser)")
+                .replace("<scope>", "parser")
+                .replace(
+                    "<english description>",
+                    "handle empty input without panicking",
+                )
+                .replace("<description>", "handle empty input without panicking"),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+const SUMMARIZE_COMMIT_PROMPT: &str = "summarize_commit";
+
+/// 生成 `summarize_commit` 提示：包含提交格式要求与当前暂存区差异
+fn summarize_commit_prompt() -> serde_json::Value {
+    let (format_hint, extra_constraints_hint, diff_settings) = {
+        let config = CONFIG.lock().unwrap();
+        let (format_hint, extra_constraints_hint) = format_hints(&config, &config.commit_format);
+        (
+            format_hint,
+            extra_constraints_hint,
+            config.diff_settings.clone(),
+        )
+    };
+    let diff = match with_git(|git| git.get_staged_diff(&diff_settings)) {
+        Ok(diff) => diff,
+        Err(e) => format!("（无法获取暂存区差异：{}）", e),
+    };
+
+    let instructions = format!(
+        "请根据下面的暂存区差异总结一个提交信息草稿，并在提交前请用户确认。\n\n\
+        ### 提交格式要求：\n{}\n\n\
+        ### 额外约束：\n{}",
+        format_hint, extra_constraints_hint
+    );
+    json!({
+        "description": "根据暂存区差异按配置的格式生成提交信息",
+        "messages": [
+            { "role": "user", "content": { "type": "text", "text": instructions } },
+            { "role": "user", "content": { "type": "text", "text": diff } }
+        ]
+    })
+}
+
+/// 暂存文件资源的 URI 前缀，后接相对于仓库根目录的路径
+const STAGED_RESOURCE_SCHEME: &str = "git-staged://";
+
+/// 读取 `git-staged://<path>` 资源，返回文件在暂存区中的内容
+fn read_resource(uri: &str) -> serde_json::Value {
+    let Some(path) = uri.strip_prefix(STAGED_RESOURCE_SCHEME) else {
+        return json!({
+            "error": { "code": -32602, "message": format!("Invalid params: unsupported resource uri `{}`", uri) }
+        });
+    };
+    match with_git(|git| git.staged_blob(path)) {
+        Ok(content) => match String::from_utf8(content) {
+            Ok(text) => json!({
+                "contents": [{ "uri": uri, "mimeType": "text/plain", "text": text }]
+            }),
+            Err(_) => json!({
+                "error": { "code": -32603, "message": format!("二进制文件不支持以文本读取: {}", path) }
+            }),
+        },
+        Err(e) => json!({
+            "error": { "code": -32002, "message": format!("Resource not found: {}", e) }
+        }),
+    }
+}
+
+/// 自检结果：仓库能否打开、HEAD 位置与已暂存文件数，不修改任何状态
+fn health() -> serde_json::Value {
+    let result = with_git(|git| {
+        let repo = git.workdir().unwrap_or(git.git_dir()).display().to_string();
+        let head = git.current_branch()?.branch;
+        let staged = git.status_summary()?.staged.len();
+        Ok((repo, head, staged))
+    });
+    match result {
+        Ok((repo, head, staged)) => json!({
+            "ok": true,
+            "repo": repo,
+            "head": head,
+            "staged": staged
+        }),
+        Err(e) => json!({
+            "ok": false,
+            "code": GitError::of(&e).map_or("GIT_ERROR", GitError::code),
+            "reason": e.to_string()
+        }),
+    }
+}
+
+/// 工具说明中随配置变化的部分：格式要求、额外约束与示例
+type ToolHints = (String, String, String);
+
+fn tool_hints() -> ToolHints {
+    let config = CONFIG.lock().unwrap();
+    let (format_hint, extra_constraints_hint) = format_hints(&config, &config.commit_format);
+    let example_hint = if config.show_example {
+        let (commit_format, _) = effective_format(&config, &config.commit_format);
+        format!(
+            "\n\n### 示例（仅示意格式，内容须根据实际变更填写）：\n{}",
+            format_example(&commit_format)
+        )
+    } else {
+        String::new()
+    };
+    (format_hint, extra_constraints_hint, example_hint)
+}
+
+/// `tools/list` 与 `tools/call` 共用的工具登记表，工具说明与处理逻辑不会脱节；
+/// 工具说明不变时复用上次构建的登记表
+fn tool_registry() -> Arc<ToolRegistry> {
+    let hints = tool_hints();
+    let mut cached = TOOL_REGISTRY.lock().unwrap();
+    match &*cached {
+        Some((built_with, registry)) if *built_with == hints => registry.clone(),
+        _ => {
+            let registry = Arc::new(build_tool_registry(hints.clone()));
+            *cached = Some((hints, registry.clone()));
+            registry
+        }
+    }
+}
+
+/// 注册所有工具及其处理函数
+fn build_tool_registry(hints: ToolHints) -> ToolRegistry {
+    let (format_hint, extra_constraints_hint, example_hint) = hints;
+
+    let mut registry = ToolRegistry::default();
+    registry.register(
+        Tool {
+            name: "check_files_status".to_string(),
+            description: "检查当前 git 状态，包括已暂存和未暂存的文件，并根据状态返回下一步工作流建议。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        |_| {
+            Ok(match with_git(|git| git.check_files_status()) {
+                Ok((has_staged, unstaged_files)) => {
+                    let text = match (has_staged, unstaged_files.is_empty()) {
+                        (false, true) => "当前没有已暂存或未暂存的文件。请先进行一些修改后再尝试提交。".to_string(),
+                        (false, false) => format!(
+                            "未暂存的文件：\n{}\n\n工作流提醒：\n请向用户展示上述文件列表。**必须**请用户确认哪些文件需要被暂存（git add）。只有在用户明确指定文件后，才可调用 `stage_files`。",
+                            unstaged_files.join("\n")
+                        ),
+                        (true, false) => format!(
+                            "已暂存的文件存在。未暂存的文件：\n{}\n\n工作流提醒：\n1. 已有已暂存的文件，可以直接进行提交。\n2. 询问用户是否需要暂存未暂存的文件。\n3. 如果用户不需暂存更多文件，直接调用 `get_staged_diff`。\n4. 如果用户需要暂存更多文件，调用 `stage_files`。",
+                            unstaged_files.join("\n")
+                        ),
+                        (true, true) => "已暂存的文件存在，且没有未暂存的文件。请直接调用 `get_staged_diff` 获取变更差异并生成提交信息草稿。".to_string(),
+                    };
+
+                    json!({ "content": [{ "type": "text", "text": text }] })
+                }
+
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_status".to_string(),
+            description: "获取工作区状态概览：已暂存、已修改未暂存、未跟踪与冲突的文件数量及列表。可在决定是否调用 get_staged_diff 之前快速了解仓库状态。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        |_| {
+            Ok(match with_git(|git| git.status_summary()) {
+                Ok(summary) => {
+                    let text = format!(
+                        "已暂存 {} 个，已修改未暂存 {} 个，未跟踪 {} 个，冲突 {} 个。",
+                        summary.staged.len(),
+                        summary.modified.len(),
+                        summary.untracked.len(),
+                        summary.conflicted.len()
+                    );
+                    json!({ "content": [
+                        { "type": "text", "text": text },
+                        { "type": "text", "text": serde_json::to_string(&summary)? }
+                    ] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "is_clean".to_string(),
+            description: "检查工作区是否干净：没有已暂存、未暂存、未跟踪或冲突的文件时返回 clean 为 true；否则列出前几个有变更的路径。适合在切换分支等操作前快速确认，需要完整列表时使用 get_status。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        |_| {
+            Ok(match with_git(|git| git.is_clean()) {
+                Ok(status) => {
+                    let text = if status.clean {
+                        "工作区干净，没有未提交的变更。".to_string()
+                    } else {
+                        let more = status.dirty_count - status.dirty.len();
+                        format!(
+                            "工作区不干净，共 {} 处变更：\n{}{}",
+                            status.dirty_count,
+                            status
+                                .dirty
+                                .iter()
+                                .map(|p| format!("- {}", p))
+                                .collect::<Vec<_>>()
+                                .join("\n"),
+                            if more > 0 {
+                                format!("\n……另有 {} 处，可用 get_status 查看完整列表。", more)
+                            } else {
+                                String::new()
+                            }
+                        )
+                    };
+                    json!({ "content": [
+                        { "type": "text", "text": text },
+                        { "type": "text", "text": serde_json::to_string(&status)? }
+                    ] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "untracked_files".to_string(),
+            description: "列出工作区中未跟踪的新文件（JSON 数组，包含未跟踪目录中的文件，已忽略 .gitignore 中的文件），可在暂存前了解新增了哪些文件。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        |_| {
+            Ok(match with_git(|git| git.untracked_files()) {
+                Ok(files) => {
+                    json!({ "content": [{ "type": "text", "text": serde_json::to_string(&files)? }] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "stage_files".to_string(),
+            description: "将指定的文件添加到 Git 暂存区。支持目录和通配符（如 `src/*.rs`），已删除的文件会暂存其删除。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "paths": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "要暂存的文件路径列表（相对于仓库根目录）"
+                    }
+                },
+                "required": ["paths"]
+            }),
+        },
+        |args| {
+            let paths = args["paths"]
+                .as_array()
+                .map(|arr| {
+                    arr.iter()
+                        .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                        .collect::<Vec<String>>()
+                })
+                .unwrap_or_default();
+            Ok(match with_git(|git| git.stage(&paths)) {
+                Ok(count) => {
+                    let text = format!(
+                        "已成功暂存 {} 个文件。\n\n提示：现在请使用 `get_staged_diff` 获取变更差异并生成提交信息草稿。",
+                        count
+                    );
+                    json!({ "content": [{ "type": "text", "text": text }] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "reset_staged".to_string(),
+            description: "取消暂存 (git reset)，将索引恢复为 HEAD 的状态，不修改工作区文件。未指定 paths 时取消全部暂存。**必须**在用户明确同意后才可调用。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "paths": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "要取消暂存的文件路径列表（相对于仓库根目录），省略时取消全部暂存"
+                    }
+                }
+            }),
+        },
+        |args| {
+            let paths = args["paths"]
+                .as_array()
+                .map(|arr| {
+                    arr.iter()
+                        .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                        .collect::<Vec<String>>()
+                })
+                .unwrap_or_default();
+            Ok(match with_git(|git| git.reset_staged(&paths)) {
+                Ok(unstaged) => {
+                    let text = format!(
+                        "已取消暂存 {} 个文件：\n{}",
+                        unstaged.len(),
+                        unstaged.join("\n")
+                    );
+                    json!({ "content": [{ "type": "text", "text": text }] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "stash_save".to_string(),
+            description: "贮藏工作区与暂存区的修改 (git stash push)，使工作区恢复到 HEAD 的状态。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "message": { "type": "string", "description": "贮藏说明，省略时使用 git 的默认说明" }
+                }
+            }),
+        },
+        |args| {
+            let message = args["message"].as_str().map(|s| s.to_string());
+            Ok(match with_git(|git| git.stash_save(message.as_deref())) {
+                Ok(oid) => {
+                    let text = format!("已贮藏当前修改 ({})。之后可调用 `stash_pop` 恢复。", oid);
+                    json!({ "content": [{ "type": "text", "text": text }] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "stash_pop".to_string(),
+            description: "恢复最近一次贮藏的修改并将其从贮藏栈中移除 (git stash pop)。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        |_| {
+            Ok(match with_git(|git| git.stash_pop()) {
+                Ok(message) => {
+                    let text = format!("已恢复贮藏: {}", message);
+                    json!({ "content": [{ "type": "text", "text": text }] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_staged_diff".to_string(),
+
+            description: format!(
+                "获取当前 git 暂存区的变更内容 (git diff --staged)。\n\n\
+                ### 工作流要求：\n\
+                1. 生成提交信息：根据变更内容总结出一个提交信息草稿。\n\
+                2. 处理 PMS 单号：\n\
+                   - 如果无法确定单号，**必须**询问用户提供。\n\
+                   - 如果用户提供了单号，将其填入提交信息。\n\
+                   - 如果用户明确表示没有单号，**必须从最终提交信息中删除整个 PMS 行**。\n\
+                3. 处理 Issue 单号：\n\
+                   - 如果无法确定单号，**必须**询问用户提供。\n\
+                   - 如果用户提供了单号，将其填入提交信息。\n\
+                   - 如果用户明确表示没有单号，**必须从最终提交信息中删除整个 Issue 行**。\n\
+                4. 用户预览与修改：展示草稿，询问用户确认。\n\
+                5. 严禁直接提交：必须得到用户明确确认后才能执行 execute_commit。\n\n\
+                ### 提交格式要求：\n{}\n\n\
+                ### 额外约束：\n{}{}",
+
+                format_hint,
+                extra_constraints_hint,
+                example_hint
+            ),
+
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "ignoreWhitespace": { "type": "boolean", "description": "忽略空白字符的差异，适用于格式化类的提交" },
+                    "wordDiff": { "type": "boolean", "description": "以词级标记输出差异：删除的部分为 [-...-]，新增的部分为 {+...+}，便于看清长行中的细小改动。默认输出行级差异" },
+                    "maxDiffBytes": { "type": "integer", "description": "返回差异的最大字节数，超出时按文件截断，0 表示不限制" },
+                    "format": format_property(),
+                    "includeUntracked": { "type": "boolean", "description": "把未跟踪的文件（遵循 .gitignore）当作新增文件一并输出，适用于尚未 git add 的新文件" },
+                    "maxFiles": { "type": "integer", "description": "变更文件数超过该值时只返回统计信息而不输出完整差异，默认 200，0 表示不限制" },
+                    "chunkBytes": { "type": "integer", "description": "把差异按行拆分为多个不超过该字节数的内容块（如 16384），适用于客户端对单个内容块大小有限制的情况；0 表示不拆分。json 格式不拆分" },
+                    "template": { "type": "string", "description": "使用的具名提交格式模板，省略时使用默认模板" }
+                }
+            }),
+        },
+        |args| {
+            let diff_settings = match diff_settings_from(args) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            let template = args["template"].as_str();
+            let template = match resolve_template(template) {
+                Ok(template) => template,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            Ok(match with_git(|git| git.review_staged(&diff_settings)) {
+                Ok(review) => {
+                    let text = "工作流提醒：\n1. 请根据上述差异总结一个提交信息草稿。\n2. **必须**询问用户确认 PMS 单号（格式如 BUG-123 或 TASK-456）。\n3. **必须**询问用户确认 GitHub Issue 号码（格式如 #123）。\n4. 展示最终提交信息并请求用户明确确认。\n5. **重要**：如果用户对信息进行了修改或提出了反馈，必须重新展示完整的修改后信息并再次请求确认。\n6. 只有在用户对最终展示的信息表示明确认可（如\"可以提交\"）后，才可调用 `execute_commit`。".to_string();
+                    let text = match &review.suggested_pms {
+                        Some(pms) => format!(
+                            "{}\n\n提示：根据当前分支名推测 PMS 单号为 {}，请向用户确认是否使用。",
+                            text, pms
+                        ),
+                        None => text,
+                    };
+                    let text = match &template {
+                        Some((name, format_hint, extra_constraints_hint)) => format!(
+                            "{}\n\n### 提交格式要求（模板 {}，优先于工具说明中的格式）：\n{}\n\n### 额外约束：\n{}",
+                            text, name, format_hint, extra_constraints_hint
+                        ),
+                        None => text,
+                    };
+                    json!({
+                        "content": diff_content(&review.diff, &text, &diff_settings),
+                        "files": review.files,
+                        "suggestedPms": review.suggested_pms,
+                        "suggestedType": review.suggested_type
+                    })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_staged_diff_by_file".to_string(),
+            description: "与 get_staged_diff 相同，但按文件拆分返回暂存区差异：每个变更文件对应一个独立的内容块，并以文件路径开头，便于逐个文件理解变更。提交信息格式与工作流要求同 get_staged_diff。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "ignoreWhitespace": { "type": "boolean", "description": "忽略空白字符的差异，适用于格式化类的提交" },
+                    "wordDiff": { "type": "boolean", "description": "以词级标记输出差异：删除的部分为 [-...-]，新增的部分为 {+...+}，便于看清长行中的细小改动。默认输出行级差异" },
+                    "maxDiffBytes": { "type": "integer", "description": "返回差异的最大字节数，超出时按文件截断，0 表示不限制" },
+                    "format": format_property(),
+                    "includeUntracked": { "type": "boolean", "description": "把未跟踪的文件（遵循 .gitignore）当作新增文件一并输出" }
+                }
+            }),
+        },
+        |args| {
+            let diff_settings = match diff_settings_from(args) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            Ok(match with_git(|git| git.get_staged_diff_by_file(&diff_settings)) {
+                Ok((files, truncated)) => {
+                    let mut content = Vec::with_capacity(files.len() + 2);
+                    for f in &files {
+                        let text = match diff_settings.format {
+                            DiffFormat::Json => serde_json::to_string(f)?,
+                            DiffFormat::Patch => format!("文件: {}\n\n{}", f.path, f.text),
+                        };
+                        content.push(json!({ "type": "text", "text": text }));
+                    }
+                    if let Some(marker) = truncated {
+                        content.push(json!({ "type": "text", "text": marker.trim() }));
+                    }
+                    content.push(json!({ "type": "text", "text": "工作流提醒：\n请逐个文件理解上述差异后总结提交信息草稿，后续流程与 `get_staged_diff` 相同，必须得到用户明确确认后才能调用 `execute_commit`。" }));
+                    json!({ "content": content })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_diff_stat".to_string(),
+            description: "获取暂存区变更的统计信息（变更文件数、新增与删除行数及每个文件的明细）。当变更较大时，可先调用此工具判断是否需要获取完整差异。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        |_| {
+            Ok(match with_git(|git| git.get_staged_stat()) {
+                Ok(stat) => {
+                    json!({ "content": [
+                        { "type": "text", "text": stat.summary() },
+                        { "type": "text", "text": serde_json::to_string(&stat)? }
+                    ] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_file_diff".to_string(),
+            description: "获取单个文件（或目录）在暂存区中的差异，适用于针对某个文件的总结，避免加载全部差异。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "文件或目录路径（相对于仓库根目录）" },
+                    "ignoreWhitespace": { "type": "boolean", "description": "忽略空白字符的差异" },
+                    "wordDiff": { "type": "boolean", "description": "以词级标记输出差异：删除的部分为 [-...-]，新增的部分为 {+...+}" },
+                    "maxDiffBytes": { "type": "integer", "description": "返回差异的最大字节数，超出时按文件截断，0 表示不限制" },
+                    "format": format_property()
+                },
+                "required": ["path"]
+            }),
+        },
+        |args| {
+            let diff_settings = match diff_settings_from(args) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            let path = args["path"].as_str().unwrap_or("");
+            if path.trim().is_empty() {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: path"),
+                ));
+            }
+            Ok(match with_git(|git| git.staged_diff_for_path(path, &diff_settings)) {
+                Ok(diff) => json!({ "content": [{ "type": "text", "text": diff }] }),
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "diff_between_refs".to_string(),
+            description: "获取两个版本（分支、标签或提交）之间的差异 (git diff from..to)，适用于总结 PR 或版本间的变更。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "from": { "type": "string", "description": "起始版本，如 main、v1.0.0 或提交 SHA" },
+                    "to": { "type": "string", "description": "目标版本，默认 HEAD" },
+                    "ignoreWhitespace": { "type": "boolean", "description": "忽略空白字符的差异" },
+                    "wordDiff": { "type": "boolean", "description": "以词级标记输出差异：删除的部分为 [-...-]，新增的部分为 {+...+}" },
+                    "maxDiffBytes": { "type": "integer", "description": "返回差异的最大字节数，超出时按文件截断，0 表示不限制" },
+                    "format": format_property()
+                },
+                "required": ["from"]
+            }),
+        },
+        |args| {
+            let diff_settings = match diff_settings_from(args) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            let from = args["from"].as_str().unwrap_or("");
+            if from.trim().is_empty() {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: from"),
+                ));
+            }
+            let to = args["to"].as_str().unwrap_or("HEAD");
+            Ok(match with_git(|git| git.diff_refs(from, to, &diff_settings)) {
+                Ok(diff) => json!({ "content": [{ "type": "text", "text": diff }] }),
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_commit_diff".to_string(),
+            description: "获取某个已有提交引入的差异 (git show <rev>)，包含提交信息，适用于总结或解释已有的提交。合并提交相对于第一个父提交比较。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "rev": { "type": "string", "description": "提交版本，如提交 SHA、HEAD~1 或标签名" },
+                    "ignoreWhitespace": { "type": "boolean", "description": "忽略空白字符的差异" },
+                    "wordDiff": { "type": "boolean", "description": "以词级标记输出差异：删除的部分为 [-...-]，新增的部分为 {+...+}" },
+                    "maxDiffBytes": { "type": "integer", "description": "返回差异的最大字节数，超出时按文件截断，0 表示不限制" },
+                    "format": format_property()
+                },
+                "required": ["rev"]
+            }),
+        },
+        |args| {
+            let diff_settings = match diff_settings_from(args) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            let rev = args["rev"].as_str().unwrap_or("");
+            if rev.trim().is_empty() {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: rev"),
+                ));
+            }
+            Ok(match with_git(|git| git.commit_diff(rev, &diff_settings)) {
+                Ok(diff) => json!({ "content": [{ "type": "text", "text": diff }] }),
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_unstaged_diff".to_string(),
+            description: "获取工作区中尚未暂存的变更内容 (git diff)，可用于帮助用户决定需要暂存哪些文件。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "ignoreWhitespace": { "type": "boolean", "description": "忽略空白字符的差异" },
+                    "wordDiff": { "type": "boolean", "description": "以词级标记输出差异：删除的部分为 [-...-]，新增的部分为 {+...+}" },
+                    "maxDiffBytes": { "type": "integer", "description": "返回差异的最大字节数，超出时按文件截断，0 表示不限制" },
+                    "format": format_property(),
+                    "chunkBytes": { "type": "integer", "description": "把差异按行拆分为多个不超过该字节数的内容块，0 表示不拆分。json 格式不拆分" }
+                }
+            }),
+        },
+        |args| {
+            let diff_settings = match diff_settings_from(args) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            Ok(match with_git(|git| git.get_unstaged_diff(&diff_settings)) {
+                Ok(diff) => {
+                    let text = "工作流提醒：\n请根据上述差异向用户说明未暂存的变更，并询问需要暂存哪些文件。只有在用户明确指定文件后，才可调用 `stage_files`。";
+                    json!({ "content": diff_content(&diff, text, &diff_settings) })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "generate_commit_message".to_string(),
+            description: "根据暂存文件的路径与变更类型启发式地生成提交标题草稿（类型、范围与标题），并返回推测依据。可作为总结提交信息的起点，但仍需结合差异内容完善。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        |_| {
+            let diff_settings = CONFIG.lock().unwrap().diff_settings.clone();
+            Ok(match with_git(|git| git.draft_commit_message(&diff_settings)) {
+                Ok(draft) => {
+                    let text = format!(
+                        "{}\n\n推测依据：\n{}\n\n提示：这只是根据文件路径推测的标题，请结合 `get_staged_diff` 的差异内容完善，并按配置的提交格式补充正文。",
+                        draft.subject,
+                        draft
+                            .evidence
+                            .iter()
+                            .map(|e| format!("- {}", e))
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    );
+                    json!({ "content": [
+                        { "type": "text", "text": text },
+                        { "type": "text", "text": serde_json::to_string(&draft)? }
+                    ] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "set_commit_format".to_string(),
+            description: "在会话中修改提交格式（与 initialize 的 commitFormat 配置项相同），修改后工具说明会随之更新。格式必须包含 `<type>`、`<english description>` 与 `Log:` 占位符。**必须**在用户明确要求时才可调用。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "format": { "type": "string", "description": "新的提交格式模板，多行以换行分隔" }
+                },
+                "required": ["format"]
+            }),
+        },
+        |args| {
+            let format = args["format"].as_str().unwrap_or("");
+            if format.trim().is_empty() {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: format"),
+                ));
+            }
+            let format: Vec<String> = format.lines().map(|l| l.to_string()).collect();
+            let missing = message::missing_format_markers(&format);
+            if !missing.is_empty() {
+                let text = format!(
+                    "提交格式缺少必需的占位符: {}，未做修改。",
+                    missing.join(", ")
+                );
+                return Ok(tool_error(&GitError::InvalidArgument.with(text)));
+            }
+
+            let effective = {
+                let mut config = CONFIG.lock().unwrap();
+                if config.commit_format != format {
+                    config.commit_format = format;
+                    notify_tools_changed();
+                }
+                format_hints(&config, &config.commit_format).0
+            };
+            let text = format!("提交格式已更新，当前生效的格式：\n{}", effective);
+            Ok(json!({ "content": [{ "type": "text", "text": text }] }))
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "validate_commit_message".to_string(),
+            description: "按配置的提交格式检查提交信息（标题格式、Body 行宽、必需的 Log/Influence 行），返回违规项列表。建议在调用 execute_commit 之前使用，并根据结果修正提交信息。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "message": { "type": "string", "description": "待检查的提交信息" }
+                },
+                "required": ["message"]
+            }),
+        },
+        |args| {
+            let msg = args["message"].as_str().unwrap_or("");
+            let violations = {
+                let config = CONFIG.lock().unwrap();
+                message::validate(msg, &config.commit_format, config.body_line_limit)
+            };
+            let text = if violations.is_empty() {
+                "提交信息符合格式要求。".to_string()
+            } else {
+                let details = violations
+                    .iter()
+                    .map(|v| match v.line {
+                        Some(line) => format!("- 第 {} 行：{}", line, v.reason),
+                        None => format!("- {}", v.reason),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!("提交信息存在以下问题，请修正后再提交：\n{}", details)
+            };
+            Ok(json!({ "content": [
+                { "type": "text", "text": text },
+                { "type": "text", "text": serde_json::to_string(&violations)? }
+            ] }))
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "lint_commit_message".to_string(),
+            description: "按 commitlint（@commitlint/config-conventional）的规则检查提交信息：类型是否在允许的集合中、主题是否小写且末尾无句号、标题长度，以及正文与 footer 前的空行。返回 {rule, severity, message} 列表，适用于使用 commitlint 的团队。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "message": { "type": "string", "description": "待检查的提交信息" }
+                },
+                "required": ["message"]
+            }),
+        },
+        |args| {
+            let msg = args["message"].as_str().unwrap_or("");
+            let issues = message::lint(msg, &CONFIG.lock().unwrap().lint_rules);
+            let text = if issues.is_empty() {
+                "提交信息通过了 commitlint 规则检查。".to_string()
+            } else {
+                let details = issues
+                    .iter()
+                    .map(|i| format!("- [{}] {}：{}", i.severity, i.rule, i.message))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!("提交信息存在以下问题：\n{}", details)
+            };
+            Ok(json!({ "content": [
+                { "type": "text", "text": text },
+                { "type": "text", "text": serde_json::to_string(&issues)? }
+            ] }))
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "execute_commit".to_string(),
+            description: "执行提交。请在用户确认了你总结的提交信息后再调用此工具。".to_string(),
+            input_schema: commit_schema(
+                json!({
+                    "message": { "type": "string", "description": "提交信息" },
+                    "wrapBody": { "type": "boolean", "description": "提交前把正文的普通段落按 bodyLineLimit 重新折行；标题、列表项与 Log:/PMS: 等 trailer 行保持不变。默认不折行" },
+                    "dryRun": { "type": "boolean", "description": "只预览不提交：执行同样的检查，返回将要创建的提交的树、父提交与追加 trailer、折行后的最终提交信息，不运行 pre-commit 钩子。默认 false" }
+                }),
+                &["message"],
+            ),
+        },
+        |args| {
+            let msg = args["message"].as_str().unwrap_or("");
+            if msg.trim().is_empty() {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: message"),
+                ));
+            }
+            let options = match commit_options(args) {
+                Ok(options) => options,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            let wrap_body = args["wrapBody"].as_bool().unwrap_or(false);
+            let msg = if wrap_body {
+                let limit = CONFIG.lock().unwrap().body_line_limit;
+                message::wrap_body(msg, limit)
+            } else {
+                msg.to_string()
+            };
+            if args["dryRun"].as_bool().unwrap_or(false) {
+                return Ok(match with_git(|git| git.preview_commit(&msg, &options)) {
+                    Ok(preview) => {
+                        let text = format!(
+                            "预览（未创建提交）：将在 {} 上提交以下信息，树 {}。\n\n{}\n\n工作流提醒：请向用户展示上述提交信息，用户确认后去掉 dryRun 再次调用 `execute_commit`。",
+                            preview.branch,
+                            preview.tree,
+                            preview.message.trim_end()
+                        );
+                        json!({ "content": [
+                            { "type": "text", "text": text },
+                            { "type": "text", "text": serde_json::to_string(&preview)? }
+                        ] })
+                    }
+                    Err(e) => tool_error(&e),
+                });
+            }
+            commit_response(with_git(|git| git.commit(&msg, &options)))
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "commit_paths".to_string(),
+            description: "只提交暂存区中指定文件的变更，其余已暂存的变更保留在暂存区中。每个路径都必须有已暂存的变更。请在用户确认了提交信息后再调用此工具。".to_string(),
+            input_schema: commit_schema(
+                json!({
+                    "paths": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "要提交的文件路径（相对于仓库根目录）"
+                    },
+                    "message": { "type": "string", "description": "提交信息" }
+                }),
+                &["paths", "message"],
+            ),
+        },
+        |args| {
+            let msg = args["message"].as_str().unwrap_or("");
+            if msg.trim().is_empty() {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: message"),
+                ));
+            }
+            let paths: Vec<String> = args["paths"]
+                .as_array()
+                .map(|arr| {
+                    arr.iter()
+                        .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            if paths.is_empty() {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: paths"),
+                ));
+            }
+            let options = match commit_options(args) {
+                Ok(options) => options,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            commit_response(with_git(|git| git.commit_paths(&paths, msg, &options)))
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "push".to_string(),
+            description: "将分支推送到远程仓库 (git push)。认证依次使用 SSH agent 与 git 凭据助手。**必须**在用户明确同意后才可调用。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "remote": { "type": "string", "description": "远程仓库名，默认使用分支配置的上游，没有上游时为 origin" },
+                    "branch": { "type": "string", "description": "要推送的分支，默认当前分支" }
+                }
+            }),
+        },
+        |args| {
+            let remote = args["remote"].as_str();
+            let branch = args["branch"].as_str();
+            Ok(match with_git(|git| git.push(remote, branch)) {
+                Ok(res) => {
+                    let text = format!(
+                        "已将分支 {} 推送到 {}/{}（远程仓库来源：{}）。",
+                        res.branch, res.remote, res.remote_branch, res.source
+                    );
+                    json!({ "content": [
+                        { "type": "text", "text": text },
+                        { "type": "text", "text": serde_json::to_string(&res)? }
+                    ] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "create_tag".to_string(),
+            description: "在当前 HEAD 上创建标签 (git tag)，可选附注标签。**必须**在用户确认标签名后才可调用。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string", "description": "标签名，如 v1.2.0" },
+                    "message": { "type": "string", "description": "标签说明，创建附注标签时必填" },
+                    "annotated": { "type": "boolean", "description": "创建附注标签，默认创建轻量标签" },
+                    "force": { "type": "boolean", "description": "覆盖已存在的同名标签" }
+                },
+                "required": ["name"]
+            }),
+        },
+        |args| {
+            let name = args["name"].as_str().unwrap_or("");
+            if name.trim().is_empty() {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: name"),
+                ));
+            }
+            let message = args["message"].as_str();
+            let annotated = args["annotated"].as_bool().unwrap_or(false);
+            let force = args["force"].as_bool().unwrap_or(false);
+            Ok(match with_git(|git| git.create_tag(name, message, annotated, force)) {
+                Ok(res) => json!({ "content": [{ "type": "text", "text": res }] }),
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_current_branch".to_string(),
+            description: "获取当前分支名，可用于推断提交的 scope 或从分支名中提取 BUG/TASK 单号。处于分离 HEAD 状态时返回缩写的提交 SHA，并将 detached 置为 true。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        |_| {
+            Ok(match with_git(|git| git.current_branch()) {
+                Ok(info) => {
+                    json!({ "content": [{ "type": "text", "text": serde_json::to_string(&info)? }] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_branches".to_string(),
+            description: "列出本地分支（可选包括远程跟踪分支），返回分支名、是否为当前分支以及跟踪的上游分支，可用于选择推送目标、比较基准或推断 scope。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "includeRemote": { "type": "boolean", "description": "同时列出远程跟踪分支（如 origin/main），默认 false" }
+                }
+            }),
+        },
+        |args| {
+            let include_remote = args["includeRemote"].as_bool().unwrap_or(false);
+            Ok(match with_git(|git| git.branches(include_remote)) {
+                Ok(branches) => {
+                    let text = if branches.is_empty() {
+                        "仓库中还没有任何分支（尚未创建第一个提交）。".to_string()
+                    } else {
+                        branches
+                            .iter()
+                            .map(|b| {
+                                let marker = if b.current { "* " } else { "  " };
+                                match &b.upstream {
+                                    Some(upstream) => format!("{}{} -> {}", marker, b.name, upstream),
+                                    None => format!("{}{}", marker, b.name),
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    };
+                    json!({ "content": [
+                        { "type": "text", "text": text },
+                        { "type": "text", "text": serde_json::to_string(&branches)? }
+                    ] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "blame_line_range".to_string(),
+            description: "获取文件指定行范围最后由哪些提交修改（git blame），返回每段的提交 SHA、作者与提交标题，可用于了解被修改代码的来历。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "文件路径（相对于仓库根目录）" },
+                    "start": { "type": "integer", "description": "起始行号，从 1 开始" },
+                    "end": { "type": "integer", "description": "结束行号（包含）" }
+                },
+                "required": ["path", "start", "end"]
+            }),
+        },
+        |args| {
+            let Some(path) = args["path"].as_str() else {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: path"),
+                ));
+            };
+            let (Some(start), Some(end)) = (
+                args["start"].as_u64(),
+                args["end"].as_u64(),
+            ) else {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: start 与 end"),
+                ));
+            };
+            Ok(match with_git(|git| git.blame(path, start as usize, end as usize)) {
+                Ok(hunks) => {
+                    json!({ "content": [{ "type": "text", "text": serde_json::to_string(&hunks)? }] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_recent_log".to_string(),
+            description: "获取最近的提交记录（标题、作者与时间），可参考仓库已有的提交风格来生成提交信息。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "count": { "type": "integer", "description": "返回的提交数量，默认 10" },
+                    "dateFormat": date_format_property()
+                }
+            }),
+        },
+        |args| {
+            let count = args["count"].as_u64().unwrap_or(10) as usize;
+            let date_format = match date_format_from(args) {
+                Ok(format) => format,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            Ok(match with_git(|git| git.recent_log(count, date_format)) {
+                Ok(entries) => {
+                    json!({ "content": [{ "type": "text", "text": serde_json::to_string(&entries)? }] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "get_last_commit".to_string(),
+            description: "获取最近一次提交（HEAD）的完整 SHA、作者、时间与完整提交信息，可在提交或修改提交后向用户展示结果。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "dateFormat": date_format_property()
+                }
+            }),
+        },
+        |args| {
+            let date_format = match date_format_from(args) {
+                Ok(format) => format,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            Ok(match with_git(|git| git.last_commit(date_format)) {
+                Ok(commit) => {
+                    json!({ "content": [{ "type": "text", "text": serde_json::to_string(&commit)? }] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "amend_commit".to_string(),
+            description: "修改上一次提交的提交信息；若暂存区有变更，会一并并入上一次提交。请在用户确认了新的提交信息后再调用此工具。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "message": { "type": "string", "description": "新的提交信息" }
+                },
+                "required": ["message"]
+            }),
+        },
+        |args| {
+            let msg = args["message"].as_str().unwrap_or("");
+            if msg.trim().is_empty() {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: message"),
+                ));
+            }
+            Ok(match with_git(|git| git.amend(msg)) {
+                Ok(res) => json!({ "content": [{ "type": "text", "text": res }] }),
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "amend_metadata".to_string(),
+            description: "只修改上一次提交的作者身份或时间，提交内容与提交信息保持不变，暂存区的变更不会并入。适用于修正错误的作者。请在用户确认后再调用此工具。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "authorName": { "type": "string", "description": "新的作者与提交者姓名，须与 authorEmail 同时提供" },
+                    "authorEmail": { "type": "string", "description": "新的作者与提交者邮箱，须与 authorName 同时提供" },
+                    "date": { "type": "string", "description": "新的作者与提交者时间，RFC 3339 格式或 Unix 时间戳；省略时保留原作者时间" }
+                }
+            }),
+        },
+        |args| {
+            let options = match commit_options(args) {
+                Ok(options) => options,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            if options.author.is_none() && options.date.is_none() {
+                return Ok(tool_error(&GitError::InvalidArgument.with(
+                    "没有需要修改的内容，请至少提供 authorName/authorEmail 或 date。",
+                )));
+            }
+            let author = options
+                .author
+                .as_ref()
+                .map(|(name, email)| (name.as_str(), email.as_str()));
+            Ok(match with_git(|git| git.amend_metadata(author, options.date)) {
+                Ok(res) => json!({ "content": [{ "type": "text", "text": res }] }),
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "undo_last_commit".to_string(),
+            description: "撤销上一次提交（相当于 git reset --soft HEAD~1），提交中的变更保留在暂存区中，可修改后重新提交。不能撤销仓库的第一个提交。**必须**在用户明确同意后才可调用。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        |_| {
+            Ok(match with_git(|git| git.undo_last_commit()) {
+                Ok(res) => json!({ "content": [{ "type": "text", "text": res }] }),
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "squash_commits".to_string(),
+            description: "把最近的 count 个提交压缩为一个新提交（相当于 git reset --soft HEAD~count 后重新提交），暂存区中已有的变更也会一并并入。count 可以等于提交总数，此时新提交成为根提交。不能包含合并提交。返回新提交与被压缩提交的标题。**必须**在用户确认了要压缩的提交与新的提交信息后才可调用。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "count": { "type": "integer", "description": "要压缩的提交数量，至少为 2" },
+                    "message": { "type": "string", "description": "压缩后新提交的提交信息" },
+                    "sign": { "type": "boolean", "description": "是否对提交进行 GPG/SSH 签名，默认沿用 git 配置 commit.gpgsign" },
+                    "skipHooks": { "type": "boolean", "description": "跳过 pre-commit 钩子，仅在紧急情况下由用户明确要求时使用" },
+                    "signoff": { "type": "boolean", "description": "以提交者身份在末尾追加 `Signed-off-by: Name <email>` trailer" },
+                    "strict": { "type": "boolean", "description": "提交前检查标题，不符合时拒绝提交。默认 true" }
+                },
+                "required": ["count", "message"]
+            }),
+        },
+        |args| {
+            let msg = args["message"].as_str().unwrap_or("");
+            if msg.trim().is_empty() {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: message"),
+                ));
+            }
+            let Some(count) = args["count"].as_u64() else {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: count"),
+                ));
+            };
+            let options = match commit_options(args) {
+                Ok(options) => options,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            Ok(
+                match with_git(|git| git.squash_commits(count as usize, msg, &options)) {
+                    Ok((res, squashed)) => {
+                        let text = format!(
+                            "已将 {} 个提交压缩为: [{} {}] {}\n\n被压缩的提交：\n{}",
+                            squashed.len(),
+                            res.branch,
+                            res.short_id,
+                            res.subject,
+                            squashed
+                                .iter()
+                                .map(|s| format!("- {}", s))
+                                .collect::<Vec<_>>()
+                                .join("\n")
+                        );
+                        json!({ "content": [
+                            { "type": "text", "text": text },
+                            { "type": "text", "text": serde_json::to_string(&json!({ "commit": res, "squashed": squashed }))? }
+                        ] })
+                    }
+                    Err(e) => tool_error(&e),
+                },
+            )
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "revert_commit".to_string(),
+            description: "撤销某个提交引入的变更（git revert），生成反向变更。commit 为 true（默认）时直接创建撤销提交，默认提交信息为 git 的标准格式 `Revert \"<标题>\"`；为 false 时只把反向变更放入暂存区，便于先总结再提交。发生冲突时保留冲突状态并列出冲突文件。要求暂存区为空，不能撤销合并提交。**必须**在用户明确同意后才可调用。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "rev": { "type": "string", "description": "要撤销的提交，如 `HEAD`、`a1b2c3d`" },
+                    "commit": { "type": "boolean", "description": "是否直接创建撤销提交，默认 true；为 false 时只暂存反向变更" },
+                    "message": { "type": "string", "description": "撤销提交的提交信息，默认为 `Revert \"<标题>\"` 加上被撤销的提交 SHA" },
+                    "sign": { "type": "boolean", "description": "是否对提交进行 GPG/SSH 签名，默认沿用 git 配置 commit.gpgsign" },
+                    "skipHooks": { "type": "boolean", "description": "跳过 pre-commit 钩子，仅在紧急情况下由用户明确要求时使用" },
+                    "signoff": { "type": "boolean", "description": "以提交者身份在末尾追加 `Signed-off-by: Name <email>` trailer" },
+                    "strict": { "type": "boolean", "description": "提交前检查自定义提交信息的标题，不符合时拒绝提交。默认 true" }
+                },
+                "required": ["rev"]
+            }),
+        },
+        |args| {
+            let Some(rev) = args["rev"].as_str() else {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: rev"),
+                ));
+            };
+            let commit = args["commit"].as_bool().unwrap_or(true);
+            let options = match commit_options(args) {
+                Ok(options) => options,
+                Err(e) => {
+                    return Ok(tool_error(&e));
+                }
+            };
+            let result =
+                with_git(|git| git.revert_commit(rev, args["message"].as_str(), commit, &options));
+            Ok(match result {
+                Ok(res) => {
+                    let text = match &res.commit {
+                        Some(c) => format!(
+                            "已撤销提交 {}: [{} {}] {}",
+                            rev, c.branch, c.short_id, c.subject
+                        ),
+                        None => format!(
+                            "已将撤销 {} 的变更放入暂存区（{} 个文件），尚未提交。确认后可用以下提交信息提交：\n\n{}",
+                            rev,
+                            res.files.len(),
+                            res.message
+                        ),
+                    };
+                    json!({ "content": [
+                        { "type": "text", "text": text },
+                        { "type": "text", "text": serde_json::to_string(&res)? }
+                    ] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "config_get".to_string(),
+            description: format!(
+                "读取 git 配置项的生效值及其所在级别（local/global/system），省略 key 时返回所有支持的配置项。仅支持：{}。",
+                ALLOWED_CONFIG_KEYS.join(", ")
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "key": { "type": "string", "enum": ALLOWED_CONFIG_KEYS, "description": "配置项名称，省略时返回全部" }
+                }
+            }),
+        },
+        |args| {
+            let entries = match args["key"].as_str() {
+                Some(key) => with_git(|git| git.config_get(key)).map(|entry| vec![entry]),
+                None => with_git(|git| {
+                    ALLOWED_CONFIG_KEYS
+                        .iter()
+                        .map(|key| git.config_get(key))
+                        .collect::<Result<Vec<_>>>()
+                }),
+            };
+            Ok(match entries {
+                Ok(entries) => {
+                    let text = entries
+                        .iter()
+                        .map(|e| match (&e.value, e.scope) {
+                            (Some(value), Some(scope)) => {
+                                format!("{} = {}（{}）", e.key, value, scope)
+                            }
+                            _ => format!("{}（未设置）", e.key),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    json!({ "content": [
+                        { "type": "text", "text": text },
+                        { "type": "text", "text": serde_json::to_string(&entries)? }
+                    ] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "config_set".to_string(),
+            description: format!(
+                "设置 git 配置项，默认写入当前仓库的配置（.git/config），global 为 true 时写入用户的全局配置。仅支持：{}。**必须**在用户明确同意后才可调用。",
+                ALLOWED_CONFIG_KEYS.join(", ")
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "key": { "type": "string", "enum": ALLOWED_CONFIG_KEYS, "description": "配置项名称" },
+                    "value": { "type": "string", "description": "配置值；commit.gpgsign 与 tag.gpgsign 为 true 或 false" },
+                    "global": { "type": "boolean", "description": "写入用户的全局配置（~/.gitconfig），默认 false" }
+                },
+                "required": ["key", "value"]
+            }),
+        },
+        |args| {
+            let Some(key) = args["key"].as_str() else {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: key"),
+                ));
+            };
+            let Some(value) = args["value"].as_str() else {
+                return Ok(tool_error(
+                    &GitError::InvalidArgument.with("缺少必需参数: value"),
+                ));
+            };
+            let global = args["global"].as_bool().unwrap_or(false);
+            Ok(match with_git(|git| git.config_set(key, value, global)) {
+                Ok(entry) => {
+                    let mut text = format!(
+                        "已将 {} 设置为 {}（写入{}配置）",
+                        entry.key,
+                        entry.value.as_deref().unwrap_or_default(),
+                        if global { "全局" } else { "仓库" }
+                    );
+                    if let Ok(effective) = with_git(|git| git.config_get(key)) {
+                        if effective.value != entry.value {
+                            text.push_str(&format!(
+                                "\n\n注意：该值被 {} 配置覆盖，当前生效的值为 {}。",
+                                effective.scope.unwrap_or_default(),
+                                effective.value.as_deref().unwrap_or_default()
+                            ));
+                        }
+                    }
+                    json!({ "content": [
+                        { "type": "text", "text": text },
+                        { "type": "text", "text": serde_json::to_string(&entry)? }
+                    ] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "verify_signature".to_string(),
+            description: "检查提交的 GPG/SSH 签名，返回是否签名、签名者身份以及是否验证通过（{ signed, signer, valid }）。验证使用本机的 GPG 密钥环，SSH 签名需要配置 gpg.ssh.allowedSignersFile。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "rev": { "type": "string", "description": "要检查的提交，默认 HEAD" }
+                }
+            }),
+        },
+        |args| {
+            let rev = args["rev"].as_str().unwrap_or("HEAD");
+            Ok(match with_git(|git| git.verify_signature(rev)) {
+                Ok(check) => {
+                    let signer = check.signer.as_deref().unwrap_or("未知");
+                    let text = match (check.signed, check.valid) {
+                        (false, _) => format!("提交 {} 没有签名。", rev),
+                        (true, true) => format!(
+                            "提交 {} 的 {} 签名有效，签名者: {}",
+                            rev,
+                            check.format.unwrap_or_default(),
+                            signer
+                        ),
+                        (true, false) => format!(
+                            "提交 {} 有 {} 签名，但未能验证通过（签名者: {}）。{}",
+                            rev,
+                            check.format.unwrap_or_default(),
+                            signer,
+                            check.detail.as_deref().unwrap_or_default()
+                        ),
+                    };
+                    json!({ "content": [
+                        { "type": "text", "text": text },
+                        { "type": "text", "text": serde_json::to_string(&check)? }
+                    ] })
+                }
+                Err(e) => tool_error(&e),
+            })
+        },
+    );
+
+    registry.register(
+        Tool {
+            name: "describe_tools".to_string(),
+            description: "以单个 JSON 内容块返回所有工具的名称、说明与输入参数 schema，适用于调试或无法完整展示 tools/list 的客户端。".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        |_| {
+            Ok(json!({ "content": [{ "type": "text", "text": serde_json::to_string_pretty(&tool_registry().tools())? }] }))
+        },
+    );
+    registry
+}
+
+/// 必须以带 id 的请求发送的方法，作为通知发送时结果无法返回，不执行并回复 `-32600`
+const REQUEST_ONLY_METHODS: &[&str] = &["initialize", "tools/list", "tools/call"];
+
+/// 处理单个 JSON-RPC 请求，返回完整的响应对象；通知不需要响应时返回 `None`
+pub fn dispatch(request: JsonRpcRequest) -> Option<serde_json::Value> {
+    let is_notification = request.id.is_none();
+    debug!("处理请求: {}", request.method);
+    if is_notification && REQUEST_ONLY_METHODS.contains(&request.method.as_str()) {
+        warn!("{} 缺少请求 id，已拒绝", request.method);
+        return Some(json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": {
+                "code": -32600,
+                "message": format!("Invalid Request: `{}` must be sent as a request with an id", request.method)
+            }
+        }));
+    }
+    let _in_flight = request.id.as_ref().map(cancel::begin);
+
+    let response_payload = match request.method.as_str() {
+        "initialize" => {
+            let mut format_validation = None;
+            let mut template_validation = serde_json::Map::new();
+            let mut default_template_validation = None;
+            let mut protocol_version = SUPPORTED_PROTOCOL_VERSIONS[0];
+            if let Some(params_val) = &request.params {
+                if let Ok(params) = serde_json::from_value::<InitializeParams>(params_val.clone()) {
+                    // 客户端请求的版本受支持时原样返回，否则返回最新版本由客户端决定是否继续
+                    match params.protocol_version.as_deref() {
+                        Some(requested) => match SUPPORTED_PROTOCOL_VERSIONS
+                            .iter()
+                            .find(|v| **v == requested)
+                        {
+                            Some(version) => protocol_version = version,
+                            None => warn!(
+                                "不支持客户端请求的协议版本 {}，使用 {}",
+                                requested, protocol_version
+                            ),
+                        },
+                        None => debug!("客户端未提供协议版本，使用 {}", protocol_version),
+                    }
+                    if let Some(options) = params.options {
+                        let mut config = CONFIG.lock().unwrap();
+                        // 先切换仓库并载入其项目配置，其余选项再覆盖项目配置
+                        if let Some(path) = options.get("repoPath").and_then(|v| v.as_str()) {
+                            config.repo_path = Some(path.to_string());
+                            if config.cli_repo_path.is_some() {
+                                info!("已通过 --repo 指定仓库，忽略 repoPath: {}", path);
+                            } else {
+                                // 恢复默认配置，避免新仓库的项目配置未设置的键沿用上一个仓库的值
+                                *config = ServerConfig {
+                                    repo_path: Some(path.to_string()),
+                                    ..ServerConfig::default()
+                                };
+                                match open_resolved(&config) {
+                                    Ok(handler) => {
+                                        apply_repo_config(&mut config, &handler);
+                                        *GIT.lock().unwrap() = Some(handler);
+                                    }
+                                    Err(e) => {
+                                        // 下次工具调用时重试打开
+                                        debug!("未能打开仓库 {}: {}", path, e);
+                                        *GIT.lock().unwrap() = None;
+                                    }
+                                }
+                            }
+                        }
+
+                        if let Some(format_val) = options.get("commitFormat") {
+                            let format = if let Some(s) = format_val.as_str() {
+                                vec![s.to_string()]
+                            } else {
+                                format_val
+                                    .as_array()
+                                    .map(|arr| {
+                                        arr.iter()
+                                            .filter_map(|v| v.as_str())
+                                            .map(|s| s.to_string())
+                                            .collect()
+                                    })
+                                    .unwrap_or_default()
+                            };
+                            let missing = message::missing_format_markers(&format);
+                            if missing.is_empty() {
+                                if config.commit_format != format {
+                                    config.commit_format = format;
+                                    notify_tools_changed();
+                                }
+                            } else {
+                                warn!(
+                                    "commitFormat 缺少必需的占位符 {:?}，已忽略并保留原有格式",
+                                    missing
+                                );
+                            }
+                            format_validation = Some(json!({
+                                "accepted": missing.is_empty(),
+                                "missingMarkers": missing
+                            }));
+                        }
+
+                        if let Some(constraints) =
+                            options.get("extraConstraints").and_then(|v| v.as_array())
+                        {
+                            config.extra_constraints = constraints
+                                .iter()
+                                .filter_map(|v| v.as_str())
+                                .map(|s| s.to_string())
+                                .collect();
+                        }
+
+                        if let Some(limit) = options.get("bodyLineLimit") {
+                            config.body_line_limit = limit
+                                .as_u64()
+                                .filter(|l| *l > 0)
+                                .map(|l| l as usize)
+                                .unwrap_or(DEFAULT_BODY_LINE_LIMIT);
+                        }
+
+                        if let Some(templates) =
+                            options.get("templates").and_then(|v| v.as_object())
+                        {
+                            for (name, format) in templates {
+                                let format = match format {
+                                    serde_json::Value::String(s) => vec![s.clone()],
+                                    serde_json::Value::Array(arr) => arr
+                                        .iter()
+                                        .filter_map(|v| v.as_str())
+                                        .map(|s| s.to_string())
+                                        .collect(),
+                                    _ => {
+                                        warn!("模板 {} 必须是字符串或字符串数组，已忽略", name);
+                                        continue;
+                                    }
+                                };
+                                let missing = message::missing_format_markers(&format);
+                                if missing.is_empty() {
+                                    config.templates.insert(name.clone(), format);
+                                } else {
+                                    warn!("模板 {} 缺少必需的占位符 {:?}，已忽略", name, missing);
+                                }
+                                template_validation.insert(
+                                    name.clone(),
+                                    json!({
+                                        "accepted": missing.is_empty(),
+                                        "missingMarkers": missing
+                                    }),
+                                );
+                            }
+                        }
+
+                        if let Some(name) = options.get("defaultTemplate").and_then(|v| v.as_str())
+                        {
+                            let known = config.templates.contains_key(name);
+                            if known {
+                                config.default_template = Some(name.to_string());
+                            } else {
+                                warn!("defaultTemplate {} 不是已配置的模板，已忽略", name);
+                            }
+                            default_template_validation = Some(json!({
+                                "accepted": known,
+                                "availableTemplates": config.templates.keys().collect::<Vec<_>>()
+                            }));
+                        }
+
+                        if let Some(require) =
+                            options.get("requireChineseBody").and_then(|v| v.as_bool())
+                        {
+                            config.require_chinese_body = require;
+                        }
+
+                        if let Some(show) = options.get("showExample").and_then(|v| v.as_bool()) {
+                            config.show_example = show;
+                        }
+
+                        if let Some(lines) = options.get("contextLines") {
+                            match lines.as_u64().filter(|l| *l <= MAX_CONTEXT_LINES) {
+                                Some(lines) => config.diff_settings.context_lines = lines as u32,
+                                None => warn!(
+                                    "contextLines 必须是 0 到 {} 之间的整数，已忽略: {}",
+                                    MAX_CONTEXT_LINES, lines
+                                ),
+                            }
+                        }
+
+                        if let Some(max_bytes) =
+                            options.get("maxDiffBytes").and_then(|v| v.as_u64())
+                        {
+                            config.diff_settings.max_bytes = max_bytes as usize;
+                        }
+
+                        if let Some(max_files) = options.get("maxFiles").and_then(|v| v.as_u64()) {
+                            config.diff_settings.max_files = max_files as usize;
+                        }
+
+                        if let Some(chunk_bytes) =
+                            options.get("chunkBytes").and_then(|v| v.as_u64())
+                        {
+                            config.diff_settings.chunk_bytes = chunk_bytes as usize;
+                        }
+
+                        if let Some(patterns) =
+                            options.get("excludePatterns").and_then(|v| v.as_array())
+                        {
+                            config.diff_settings.exclude_patterns = patterns
+                                .iter()
+                                .filter_map(|v| v.as_str())
+                                .map(|s| s.to_string())
+                                .collect();
+                        }
+
+                        if let Some(threshold) = options.get("renameThreshold") {
+                            match threshold.as_u64().filter(|t| *t <= MAX_RENAME_THRESHOLD) {
+                                Some(threshold) => {
+                                    config.diff_settings.rename_threshold = Some(threshold as u16)
+                                }
+                                None => warn!(
+                                    "renameThreshold 必须是 0 到 {} 之间的整数，已忽略: {}",
+                                    MAX_RENAME_THRESHOLD, threshold
+                                ),
+                            }
+                        }
+
+                        if let Some(types) = options.get("lintTypes").and_then(|v| v.as_array()) {
+                            config.lint_rules.allowed_types = types
+                                .iter()
+                                .filter_map(|v| v.as_str())
+                                .map(|s| s.to_string())
+                                .collect();
+                        }
+
+                        if let Some(max_length) = options
+                            .get("lintHeaderMaxLength")
+                            .and_then(|v| v.as_u64())
+                            .filter(|l| *l > 0)
+                        {
+                            config.lint_rules.header_max_length = max_length as usize;
+                        }
+
+                        if let Some(key) = options.get("signingKey").and_then(|v| v.as_str()) {
+                            config.signing_key = Some(key.to_string());
+                        }
+
+                        if let Some(retries) =
+                            options.get("indexLockRetries").and_then(|v| v.as_u64())
+                        {
+                            config.index_lock_retries = retries as u32;
+                            if let Some(git) = GIT.lock().unwrap().as_mut() {
+                                git.set_index_lock_retries(config.index_lock_retries);
+                            }
+                        }
+                    }
+                }
+            }
+
+            let mut server_info = json!({
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION")
+            });
+            // 自定义提交格式的校验结果，仅在客户端提供了 commitFormat 时返回
+            if let Some(validation) = format_validation {
+                server_info["commitFormatValidation"] = validation;
+            }
+            // 各模板与默认模板的校验结果，仅在客户端提供了 templates 或 defaultTemplate 时返回
+            if !template_validation.is_empty() {
+                server_info["templateValidation"] = template_validation.into();
+            }
+            if let Some(validation) = default_template_validation {
+                server_info["defaultTemplateValidation"] = validation;
+            }
+
+            Some(json!({
+                "protocolVersion": protocol_version,
+                "capabilities": {
+                    "tools": {
+                        "listChanged": true
+                    },
+                    "logging": {},
+                    "resources": {},
+                    "prompts": {}
+                },
+                "serverInfo": server_info
+            }))
+        }
+        "ping" => Some(json!({})),
+        "git-summarizer/health" => Some(health()),
+        "shutdown" => {
+            info!("收到 shutdown 请求，等待 exit 通知");
+            SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+            Some(json!({}))
+        }
+        "exit" => {
+            if !SHUTDOWN_REQUESTED.load(Ordering::SeqCst) {
+                warn!("未收到 shutdown 就收到了 exit 通知");
+            }
+            EXIT_REQUESTED.store(true, Ordering::SeqCst);
+            None
+        }
+        "logging/setLevel" => {
+            let level = request
+                .params
+                .as_ref()
+                .and_then(|p| p["level"].as_str())
+                .unwrap_or_default();
+            match mcp_level_to_filter(level) {
+                Some(directive) => {
+                    let reloaded = LOG_FILTER
+                        .get()
+                        .map(|handle| handle.reload(EnvFilter::new(directive)));
+                    match reloaded {
+                        Some(Err(e)) => {
+                            Some(json!({ "error": { "code": -32603, "message": e.to_string() } }))
+                        }
+                        _ => {
+                            info!("日志级别已调整为 {}", level);
+                            Some(json!({}))
+                        }
+                    }
+                }
+                None => Some(json!({
+                    "error": { "code": -32602, "message": format!("Invalid params: unknown log level `{}`", level) }
+                })),
+            }
+        }
+        "notifications/cancelled" => {
+            let request_id = request.params.as_ref().map(|p| &p["requestId"]);
+            match request_id {
+                Some(id) if cancel::cancel(id) => info!("请求 {} 已被客户端取消", id),
+                Some(id) => debug!("要取消的请求 {} 已处理完毕", id),
+                None => warn!("notifications/cancelled 缺少 requestId"),
+            }
+            None
+        }
+        "notifications/initialized" => {
+            info!("客户端已确认初始化");
+            INITIALIZED.store(true, Ordering::SeqCst);
+            None
+        }
+        "tools/list" => Some(json!({ "tools": tool_registry().tools() })),
+        "resources/list" => match with_git(|git| git.staged_paths()) {
+            Ok(paths) => {
+                let resources: Vec<Resource> = paths
+                    .into_iter()
+                    .map(|path| Resource {
+                        uri: format!("{}{}", STAGED_RESOURCE_SCHEME, path),
+                        name: path,
+                        mime_type: "text/plain".to_string(),
+                    })
+                    .collect();
+                Some(json!({ "resources": resources }))
+            }
+            Err(e) => Some(json!({ "error": { "code": -32603, "message": e.to_string() } })),
+        },
+        "resources/read" => {
+            match serde_json::from_value::<ReadResourceParams>(
+                request.params.clone().unwrap_or_default(),
+            ) {
+                Ok(params) => Some(read_resource(&params.uri)),
+                Err(e) => Some(json!({
+                    "error": { "code": -32602, "message": format!("Invalid params: {}", e) }
+                })),
+            }
+        }
+        "prompts/list" => Some(json!({
+            "prompts": [{
+                "name": SUMMARIZE_COMMIT_PROMPT,
+                "description": "根据暂存区差异按配置的提交格式生成提交信息草稿",
+                "arguments": []
+            }]
+        })),
+        "prompts/get" => {
+            let name = request
+                .params
+                .as_ref()
+                .and_then(|p| p["name"].as_str())
+                .unwrap_or_default();
+            if name == SUMMARIZE_COMMIT_PROMPT {
+                Some(summarize_commit_prompt())
+            } else {
+                Some(json!({
+                    "error": { "code": -32602, "message": format!("Invalid params: unknown prompt `{}`", name) }
+                }))
+            }
+        }
+        "tools/call" => Some(tools_call(request.params.clone())),
+        _ => {
+            if is_notification {
+                None
+            } else {
+                Some(json!({ "error": { "code": -32601, "message": "Method not found" } }))
+            }
+        }
+    };
+
+    // 已取消的请求不再发送响应
+    if cancel::is_cancelled() {
+        return None;
+    }
+    let (Some(payload), Some(id)) = (response_payload, request.id) else {
+        return None;
+    };
+
+    let mut response_obj = serde_json::Map::new();
+    response_obj.insert("jsonrpc".to_string(), json!("2.0"));
+    response_obj.insert("id".to_string(), id);
+
+    if let Some(error) = payload.get("error") {
+        response_obj.insert("error".to_string(), error.clone());
+    } else {
+        response_obj.insert("result".to_string(), payload);
+    }
+
+    Some(serde_json::Value::Object(response_obj))
+}
+
+/// 无法解析的消息对应的 `-32700` 错误响应；尽量从原始文本中找回请求 id，找不到时为 null
+pub fn parse_error_response(raw: &str, error: &serde_json::Error) -> serde_json::Value {
+    let id = RAW_ID_PATTERN
+        .captures(raw)
+        .and_then(|c| serde_json::from_str::<serde_json::Value>(&c[1]).ok())
+        .unwrap_or(serde_json::Value::Null);
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": -32700, "message": format!("Parse error: {}", error) }
+    })
+}
+
+/// 处理一条 JSON-RPC 消息（单个请求或批量请求），返回需要发送的响应
+pub fn dispatch_message(message: serde_json::Value) -> Option<serde_json::Value> {
+    if let serde_json::Value::Array(batch) = message {
+        // 空的批量请求本身就是无效请求，只回复一个错误
+        if batch.is_empty() {
+            return Some(invalid_request_response(
+                &serde_json::Value::Null,
+                "empty batch",
+            ));
+        }
+        // 批量请求：按顺序处理，通知不产生响应
+        let responses: Vec<serde_json::Value> =
+            batch.into_iter().filter_map(dispatch_single).collect();
+        if responses.is_empty() {
+            return None;
+        }
+        return Some(serde_json::Value::Array(responses));
+    }
+
+    dispatch_single(message)
+}
+
+/// 处理单个请求；是合法 JSON 但不是合法请求时返回 `-32600` 错误，并尽量带上其中的 id
+fn dispatch_single(message: serde_json::Value) -> Option<serde_json::Value> {
+    let id = message
+        .get("id")
+        .cloned()
+        .unwrap_or(serde_json::Value::Null);
+    match serde_json::from_value::<JsonRpcRequest>(message) {
+        Ok(request) if request.jsonrpc == "2.0" => dispatch(request),
+        Ok(request) => {
+            warn!("无效请求: jsonrpc 版本为 {}", request.jsonrpc);
+            Some(invalid_request_response(
+                &id,
+                &format!("unsupported jsonrpc version `{}`", request.jsonrpc),
+            ))
+        }
+        Err(e) => {
+            warn!("无效请求: {}", e);
+            Some(invalid_request_response(&id, &e.to_string()))
+        }
+    }
+}
+
+fn invalid_request_response(id: &serde_json::Value, reason: &str) -> serde_json::Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": -32600, "message": format!("Invalid Request: {}", reason) }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::env_lock;
+
+    #[test]
+    fn malformed_tools_call_params_are_invalid_params() {
+        let response = tools_call(Some(json!({ "arguments": {} })));
+        assert_eq!(response["error"]["code"], -32602);
+        assert!(response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid params"));
+
+        let response = tools_call(None);
+        assert_eq!(response["error"]["code"], -32602);
+    }
+
+    #[test]
+    fn commit_paths_declares_the_same_commit_options_as_execute_commit() {
+        let registry = tool_registry();
+        let tools = registry.tools();
+        let properties = |name: &str| {
+            let tool = tools.iter().find(|tool| tool.name == name).unwrap();
+            tool.input_schema["properties"].as_object().unwrap().clone()
+        };
+        let execute_commit = properties("execute_commit");
+        let commit_paths = properties("commit_paths");
+        for key in [
+            "sign",
+            "coAuthors",
+            "skipHooks",
+            "allowEmpty",
+            "signoff",
+            "authorName",
+            "authorEmail",
+            "date",
+            "strict",
+        ] {
+            assert_eq!(commit_paths.get(key), execute_commit.get(key), "{}", key);
+            assert!(commit_paths.contains_key(key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn commit_tools_without_message_are_tool_errors() {
+        for name in ["execute_commit", "amend_commit"] {
+            for arguments in [json!({}), json!({ "message": "  " })] {
+                let response = tools_call(Some(json!({ "name": name, "arguments": arguments })));
+                assert_eq!(response["isError"], true);
+                let text = response["content"][0]["text"].as_str().unwrap();
+                assert!(text.contains("message"), "{}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn batch_returns_one_array_of_responses() {
+        let response = dispatch_message(json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} },
+            { "jsonrpc": "2.0", "method": "notifications/initialized" },
+            { "jsonrpc": "2.0", "id": 2, "method": "tools/list" }
+        ]))
+        .unwrap();
+
+        let batch = response.as_array().unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0]["id"], 1);
+        assert!(batch[0]["result"]["serverInfo"].is_object());
+        assert_eq!(batch[1]["id"], 2);
+        assert!(batch[1]["result"]["tools"].is_array());
+    }
+
+    #[test]
+    fn batch_of_notifications_sends_nothing() {
+        let response = dispatch_message(json!([
+            { "jsonrpc": "2.0", "method": "notifications/initialized" }
+        ]));
+        assert!(response.is_none());
+    }
+
+    #[test]
+    fn empty_batch_is_an_invalid_request() {
+        let response = dispatch_message(json!([])).unwrap();
+        assert_eq!(response["id"], serde_json::Value::Null);
+        assert_eq!(response["error"]["code"], -32600);
+    }
+
+    #[test]
+    fn server_info_reports_package_name_and_version() {
+        let response = dispatch_message(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {}
+        }))
+        .unwrap();
+
+        let info = &response["result"]["serverInfo"];
+        assert_eq!(info["name"], env!("CARGO_PKG_NAME"));
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn parse_error_recovers_request_id_when_possible() {
+        for (raw, id) in [
+            (
+                "{\"jsonrpc\": \"2.0\", \"id\": 7, \"method\": \"ping\",",
+                json!(7),
+            ),
+            ("{\"id\": \"nine\", \"method\": ", json!("nine")),
+            ("not json at all", serde_json::Value::Null),
+        ] {
+            let error = serde_json::from_str::<serde_json::Value>(raw).unwrap_err();
+            let response = parse_error_response(raw, &error);
+            assert_eq!(response["id"], id, "{}", raw);
+            assert_eq!(response["error"]["code"], -32700);
+        }
+    }
+
+    #[test]
+    fn protocol_version_is_negotiated() {
+        let initialize = |version: &str| {
+            dispatch_message(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "protocolVersion": version }
+            }))
+            .unwrap()
+        };
+        let oldest = *SUPPORTED_PROTOCOL_VERSIONS.last().unwrap();
+
+        assert_eq!(initialize(oldest)["result"]["protocolVersion"], oldest);
+        assert_eq!(
+            initialize("1999-01-01")["result"]["protocolVersion"],
+            SUPPORTED_PROTOCOL_VERSIONS[0]
+        );
+    }
+
+    #[test]
+    fn repo_sources_follow_precedence() {
+        let _env = env_lock();
+        let mut config = CONFIG.lock().unwrap();
+        let saved = (config.cli_repo_path.take(), config.repo_path.take());
+        std::env::set_var(REPO_ENV_VAR, "/from/env");
+        config.cli_repo_path = Some("/from/cli".to_string());
+        config.repo_path = Some("/from/initialize".to_string());
+        assert_eq!(resolve_repo(&config).0.as_deref(), Some("/from/cli"));
+
+        config.cli_repo_path = None;
+        assert_eq!(resolve_repo(&config).0.as_deref(), Some("/from/initialize"));
+
+        config.repo_path = None;
+        assert_eq!(resolve_repo(&config).0.as_deref(), Some("/from/env"));
+
+        std::env::remove_var(REPO_ENV_VAR);
+        std::env::set_var("GIT_DIR", "/from/git-dir");
+        assert_eq!(resolve_repo(&config).0, None);
+
+        std::env::remove_var("GIT_DIR");
+        assert_eq!(resolve_repo(&config).0.as_deref(), Some("."));
+        (config.cli_repo_path, config.repo_path) = saved;
+    }
+
+    #[test]
+    fn unknown_diff_format_is_rejected() {
+        let settings = diff_settings_from(&json!({ "format": "json" })).unwrap();
+        assert!(matches!(settings.format, DiffFormat::Json));
+        let settings = diff_settings_from(&json!({ "format": "patch" })).unwrap();
+        assert!(matches!(settings.format, DiffFormat::Patch));
+
+        let err = diff_settings_from(&json!({ "format": "xml" })).unwrap_err();
+        assert_eq!(GitError::of(&err), Some(GitError::InvalidArgument));
+        let message = err.to_string();
+        assert!(
+            message.contains("patch") && message.contains("json"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn tool_registry_is_rebuilt_only_when_descriptions_change() {
+        let _env = env_lock();
+        let registry = tool_registry();
+        assert!(Arc::ptr_eq(&registry, &tool_registry()));
+
+        let show_example = {
+            let mut config = CONFIG.lock().unwrap();
+            config.show_example = !config.show_example;
+            !config.show_example
+        };
+        let rebuilt = tool_registry();
+        CONFIG.lock().unwrap().show_example = show_example;
+        assert!(!Arc::ptr_eq(&registry, &rebuilt));
+    }
+}
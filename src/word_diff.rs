@@ -0,0 +1,212 @@
+/// 超过该规模（删除词数 × 新增词数）的变更块不做词级比较，保留原始的行级输出
+const MAX_COMPARISONS: usize = 1_000_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// 把单个文件的 patch 改写为类似 `git diff --word-diff=plain` 的形式：相邻的删除行与新增行
+/// 合并输出，行内被删除的部分标记为 `[-...-]`，新增的部分标记为 `{+...+}`
+pub fn render(patch: &str) -> String {
+    let mut output = String::with_capacity(patch.len());
+    let mut in_hunk = false;
+    let mut removed: Vec<&str> = Vec::new();
+    let mut added: Vec<&str> = Vec::new();
+
+    for line in patch.split_inclusive('\n') {
+        if line.starts_with("@@") {
+            flush(&mut output, &mut removed, &mut added);
+            in_hunk = true;
+            output.push_str(line);
+            continue;
+        }
+        if !in_hunk {
+            output.push_str(line);
+            continue;
+        }
+
+        match line.as_bytes().first() {
+            Some(b'-') => {
+                // 新增行之后又出现删除行，说明是下一个变更块
+                if !added.is_empty() {
+                    flush(&mut output, &mut removed, &mut added);
+                }
+                removed.push(&line[1..]);
+            }
+            Some(b'+') => added.push(&line[1..]),
+            // `\ No newline at end of file` 不属于文件内容
+            Some(b'\\') => {}
+            _ => {
+                flush(&mut output, &mut removed, &mut added);
+                output.push_str(line.strip_prefix(' ').unwrap_or(line));
+            }
+        }
+    }
+    flush(&mut output, &mut removed, &mut added);
+
+    output
+}
+
+/// 输出一个变更块并清空缓存的删除行与新增行
+fn flush(output: &mut String, removed: &mut Vec<&str>, added: &mut Vec<&str>) {
+    if removed.is_empty() && added.is_empty() {
+        return;
+    }
+
+    let old = removed.concat();
+    let new = added.concat();
+    removed.clear();
+    added.clear();
+
+    let old_tokens = tokenize(&old);
+    let new_tokens = tokenize(&new);
+    if old_tokens.len() * new_tokens.len() > MAX_COMPARISONS {
+        push_marked(output, Op::Delete, &old);
+        push_marked(output, Op::Insert, &new);
+        return;
+    }
+
+    let mut run = String::new();
+    let mut run_op = Op::Equal;
+    for (op, token) in diff_tokens(&old_tokens, &new_tokens) {
+        if op != run_op && !run.is_empty() {
+            push_marked(output, run_op, &run);
+            run.clear();
+        }
+        run_op = op;
+        run.push_str(token);
+    }
+    push_marked(output, run_op, &run);
+
+    if !output.ends_with('\n') {
+        output.push('\n');
+    }
+}
+
+/// 按操作类型输出一段文本；标记不跨行，每行各自闭合，保持按行阅读
+fn push_marked(output: &mut String, op: Op, text: &str) {
+    let (open, close) = match op {
+        Op::Equal => {
+            output.push_str(text);
+            return;
+        }
+        Op::Delete => ("[-", "-]"),
+        Op::Insert => ("{+", "+}"),
+    };
+    for piece in text.split_inclusive('\n') {
+        let (content, newline) = match piece.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (piece, ""),
+        };
+        if !content.is_empty() {
+            output.push_str(open);
+            output.push_str(content);
+            output.push_str(close);
+        }
+        output.push_str(newline);
+    }
+}
+
+/// 拆分为单词（字母、数字与下划线）、连续空白、换行与单个标点符号
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        let same_class: fn(char) -> bool = if c.is_ascii_alphanumeric() || c == '_' {
+            |n| n.is_ascii_alphanumeric() || n == '_'
+        } else if c != '\n' && c.is_whitespace() {
+            |n| n != '\n' && n.is_whitespace()
+        } else {
+            |_| false
+        };
+        while let Some((_, next)) = chars.peek() {
+            if !same_class(*next) {
+                break;
+            }
+            chars.next();
+        }
+        let end = chars.peek().map_or(text.len(), |(i, _)| *i);
+        tokens.push(&text[idx..end]);
+    }
+    tokens
+}
+
+/// 基于最长公共子序列的词级差异
+fn diff_tokens<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    let (n, m) = (old.len(), new.len());
+    // lcs[i][j] 为 old[i..] 与 new[j..] 的最长公共子序列长度
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push((Op::Equal, old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push((Op::Delete, old[i]));
+            i += 1;
+        } else {
+            ops.push((Op::Insert, new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|t| (Op::Delete, *t)));
+    ops.extend(new[j..].iter().map(|t| (Op::Insert, *t)));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_words_are_marked_inline() {
+        let patch = "diff --git a/src/lib.rs b/src/lib.rs\n\
+            --- a/src/lib.rs\n\
+            +++ b/src/lib.rs\n\
+            @@ -1,2 +1,2 @@\n \
+            fn unchanged() {}\n\
+            -let total = old_name(count);\n\
+            +let total = new_name(count);\n";
+
+        assert_eq!(
+            render(patch),
+            "diff --git a/src/lib.rs b/src/lib.rs\n\
+            --- a/src/lib.rs\n\
+            +++ b/src/lib.rs\n\
+            @@ -1,2 +1,2 @@\n\
+            fn unchanged() {}\n\
+            let total = [-old_name-]{+new_name+}(count);\n"
+        );
+    }
+
+    #[test]
+    fn markers_are_closed_at_each_line_end() {
+        let patch = "@@ -1,2 +1,2 @@\n-gone\n-also gone\n+fresh\n";
+        assert_eq!(
+            render(patch),
+            "@@ -1,2 +1,2 @@\n[-gone-]\n[-also gone-]{+fresh+}\n"
+        );
+    }
+
+    #[test]
+    fn missing_newline_marker_is_dropped() {
+        let patch =
+            "@@ -1 +1 @@\n-a b\n\\ No newline at end of file\n+a c\n\\ No newline at end of file\n";
+        assert_eq!(render(patch), "@@ -1 +1 @@\na [-b-]{+c+}\n");
+    }
+}